@@ -0,0 +1,374 @@
+
+
+use std::collections::HashMap;
+
+use noodles::sam;
+use noodles::sam::record::cigar::op::kind::Kind;
+
+// walks the cigar of a read to know which read base (if any) is aligned to
+// each reference position. positions are 1-based as in the rest of the
+// crate, read indices are 0-based indices into the SEQ field.
+
+/// One cigar operation placed on the reference and on the read. For
+/// insertions `ref_start` is the reference position after the insertion.
+struct AlignedBlock {
+    kind: Kind,
+    len: usize,
+    ref_start: usize,
+    read_start: usize,
+}
+
+fn aligned_blocks(record: &sam::alignment::Record) -> Vec<AlignedBlock> {
+    let mut blocks = Vec::new();
+    let mut ref_cursor = match record.alignment_start() {
+        Some(position) => usize::from(position),
+        None => return blocks,
+    };
+    let mut read_cursor = 0;
+
+    for op in record.cigar().iter() {
+        let kind = op.kind();
+        blocks.push(AlignedBlock {
+            kind,
+            len: op.len(),
+            ref_start: ref_cursor,
+            read_start: read_cursor,
+        });
+        if kind.consumes_reference() {
+            ref_cursor += op.len();
+        }
+        if kind.consumes_read() {
+            read_cursor += op.len();
+        }
+    }
+    blocks
+}
+
+/// What a read has at a reference position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpanBase {
+    /// index of the read base aligned to the position
+    Base(usize),
+    /// index of a read base inserted after the previous position
+    Inserted(usize),
+    Deletion,
+    Skip,
+}
+
+/// Read bases over the reference positions `start..=end`, with the
+/// insertions between them. The insertion after `end` is only included with
+/// `trailing_insertion` (needed for vcf insertions, anchored at `end`).
+/// Returns None if the read does not cover the whole span.
+pub fn span_bases(record: &sam::alignment::Record, start: usize, end: usize, trailing_insertion: bool) -> Option<Vec<SpanBase>> {
+    let mut span = Vec::new();
+    let mut next_position = start;
+
+    for block in aligned_blocks(record) {
+        if next_position > end {
+            // only an insertion right after the span can still be added
+            if trailing_insertion && block.kind == Kind::Insertion && block.ref_start == end + 1 {
+                span.extend((block.read_start..block.read_start + block.len).map(SpanBase::Inserted));
+            }
+            if block.kind.consumes_reference() {
+                break;
+            }
+            continue;
+        }
+
+        match block.kind {
+            Kind::Insertion => {
+                // insertions before the first position belong to the previous base
+                if block.ref_start > start && block.ref_start <= end {
+                    span.extend((block.read_start..block.read_start + block.len).map(SpanBase::Inserted));
+                }
+            },
+            Kind::Match | Kind::SequenceMatch | Kind::SequenceMismatch | Kind::Deletion | Kind::Skip => {
+                let block_end = block.ref_start + block.len - 1;
+                if block_end < next_position {
+                    continue;
+                }
+                if block.ref_start > next_position {
+                    return None;
+                }
+                let last = block_end.min(end);
+                for position in next_position..=last {
+                    span.push(match block.kind {
+                        Kind::Deletion => SpanBase::Deletion,
+                        Kind::Skip => SpanBase::Skip,
+                        _ => SpanBase::Base(block.read_start + position - block.ref_start),
+                    });
+                }
+                next_position = last + 1;
+            },
+            Kind::SoftClip | Kind::HardClip | Kind::Pad => {},
+        }
+    }
+
+    if next_position > end {
+        Some(span)
+    } else {
+        None
+    }
+}
+
+/// Allele supported by a read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlleleSupport {
+    Ref,
+    /// 0-based index of the alternative allele
+    Alt(usize),
+    Other,
+}
+
+/// Allele of a read and the index of its first base in the read, None if
+/// the read has no base at the variant (the whole allele is deleted).
+#[derive(Clone, Copy)]
+pub struct ReadAllele {
+    pub support: AlleleSupport,
+    pub read_index: Option<usize>,
+}
+
+/// Compares the read sequence over the reference allele with each allele of
+/// the variant. Returns None if the read does not cover the variant.
+pub fn classify_read(record: &sam::alignment::Record, position: usize, reference: &[u8], alternates: &[Vec<u8>]) -> Option<ReadAllele> {
+    let end = position + reference.len() - 1;
+    let trailing_insertion = alternates.iter().any(|alt| alt.len() > reference.len());
+    let span = span_bases(record, position, end, trailing_insertion)?;
+
+    let sequence = record.sequence();
+    let mut haplotype: Vec<u8> = Vec::with_capacity(span.len());
+    let mut read_index = None;
+    let mut has_skip = false;
+    for base in span {
+        match base {
+            SpanBase::Base(idx) | SpanBase::Inserted(idx) => {
+                let read_base = sequence.as_ref().get(idx).map(|x| u8::from(*x))?;
+                haplotype.push(read_base.to_ascii_uppercase());
+                read_index.get_or_insert(idx);
+            },
+            SpanBase::Deletion => {},
+            SpanBase::Skip => has_skip = true,
+        }
+    }
+
+    let matches = |allele: &[u8]| allele.len() == haplotype.len() && allele.iter().zip(&haplotype).all(|(a, b)| a.eq_ignore_ascii_case(b));
+    let support = if has_skip {
+        AlleleSupport::Other
+    } else if matches(reference) {
+        AlleleSupport::Ref
+    } else {
+        match alternates.iter().position(|alt| matches(alt)) {
+            Some(idx) => AlleleSupport::Alt(idx),
+            None => AlleleSupport::Other,
+        }
+    };
+    Some(ReadAllele { support, read_index })
+}
+
+/// Sequencing cycle (0-based, from the 5' end of the read) of a base in
+/// SEQ and the length of the read, hard clipped bases included.
+pub fn read_cycle(record: &sam::alignment::Record, read_index: usize) -> (usize, usize) {
+    let cigar = record.cigar();
+    let hard_clip = |op: Option<&sam::record::cigar::Op>| match op {
+        Some(op) if op.kind() == Kind::HardClip => op.len(),
+        _ => 0,
+    };
+    let leading = hard_clip(cigar.first());
+    let trailing = if cigar.len() > 1 { hard_clip(cigar.last()) } else { 0 };
+    let length = record.sequence().len() + leading + trailing;
+
+    // reverse strand reads are stored reverse complemented, their first
+    // cycle is the last base of SEQ (or of the trailing hard clip)
+    let cycle = if record.flags().is_reverse_complemented() {
+        record.sequence().len() - 1 - read_index + trailing
+    } else {
+        leading + read_index
+    };
+    (cycle, length)
+}
+
+/// Orientation of the read in its pair, F1R2 for first reads on the forward
+/// strand and second reads on the reverse strand, F2R1 for the opposite.
+#[derive(Clone, Copy, PartialEq)]
+pub enum PairOrientation {
+    F1R2,
+    F2R1,
+}
+
+pub fn pair_orientation(record: &sam::alignment::Record) -> Option<PairOrientation> {
+    let flags = record.flags();
+    if !flags.is_segmented() {
+        return None;
+    }
+    let reverse = flags.is_reverse_complemented();
+    match (flags.is_first_segment(), flags.is_last_segment()) {
+        (true, false) if !reverse => Some(PairOrientation::F1R2),
+        (true, false) => Some(PairOrientation::F2R1),
+        (false, true) if reverse => Some(PairOrientation::F1R2),
+        (false, true) => Some(PairOrientation::F2R1),
+        _ => None,
+    }
+}
+
+/// Read name of the primary alignment of a paired read with both mates
+/// mapped, and whether it is the first segment. Both mates of a fragment
+/// have the same name.
+pub fn mate_key(record: &sam::alignment::Record) -> Option<(Vec<u8>, bool)> {
+    let flags = record.flags();
+    if !flags.is_segmented() || flags.is_mate_unmapped() || flags.is_secondary() || flags.is_supplementary() {
+        return None;
+    }
+    let name: &[u8] = record.read_name()?.as_ref();
+    Some((name.to_vec(), flags.is_first_segment()))
+}
+
+/// Observations of the reads at one position counted by fragment: when
+/// both mates of a pair are found (see `mate_key`) they are replaced by the
+/// observation returned by `merge`, in the place of the first one.
+pub fn merge_mates<T>(
+    observations: Vec<T>,
+    key: impl Fn(&T) -> Option<(Vec<u8>, bool)>,
+    mut merge: impl FnMut(T, T) -> T,
+) -> Vec<T> {
+    let mut merged: Vec<Option<T>> = Vec::with_capacity(observations.len());
+    let mut unmatched: HashMap<Vec<u8>, (usize, bool)> = HashMap::new();
+    for observation in observations {
+        let (name, first_segment) = match key(&observation) {
+            Some(key) => key,
+            None => {
+                merged.push(Some(observation));
+                continue;
+            },
+        };
+        match unmatched.get(&name) {
+            Some(&(idx, mate_first_segment)) if mate_first_segment != first_segment => {
+                let mate = merged[idx].take().unwrap();
+                merged[idx] = Some(merge(mate, observation));
+                unmatched.remove(&name);
+            },
+            // other alignments of the same mate are kept apart, the mate is
+            // merged with the first one
+            Some(_) => merged.push(Some(observation)),
+            None => {
+                unmatched.insert(name, (merged.len(), first_segment));
+                merged.push(Some(observation));
+            },
+        }
+    }
+    merged.into_iter().flatten().collect()
+}
+
+/// Edit distance of a read against the reference (as the NM tag):
+/// mismatched, inserted and deleted bases. `reference` holds the reference
+/// bases from `reference_start` (1-based) and has to cover the alignment.
+pub fn edit_distance(record: &sam::alignment::Record, reference: &[u8], reference_start: usize) -> Option<usize> {
+    let sequence = record.sequence().as_ref();
+    let mut distance = 0;
+    for block in aligned_blocks(record) {
+        match block.kind {
+            Kind::Match | Kind::SequenceMatch | Kind::SequenceMismatch => {
+                let offset = block.ref_start.checked_sub(reference_start)?;
+                for idx in 0..block.len {
+                    let read_base = u8::from(*sequence.get(block.read_start + idx)?);
+                    let reference_base = *reference.get(offset + idx)?;
+                    if !read_base.eq_ignore_ascii_case(&reference_base) {
+                        distance += 1;
+                    }
+                }
+            },
+            Kind::Insertion | Kind::Deletion => distance += block.len,
+            Kind::Skip | Kind::SoftClip | Kind::HardClip | Kind::Pad => {},
+        }
+    }
+    Some(distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::read;
+
+    use SpanBase::{Base, Deletion, Inserted, Skip};
+
+    #[test]
+    fn span_bases_follows_the_cigar() {
+        // cigar and sequence of a read at 100, span and expected bases
+        let cases = [
+            ("5M2I5M", "ACGTATTCGTAC", 104, 106, false, Some(vec![Base(4), Inserted(5), Inserted(6), Base(7), Base(8)])),
+            // the insertion after the last position, only when asked
+            ("5M2I5M", "ACGTATTCGTAC", 103, 104, false, Some(vec![Base(3), Base(4)])),
+            ("5M2I5M", "ACGTATTCGTAC", 103, 104, true, Some(vec![Base(3), Base(4), Inserted(5), Inserted(6)])),
+            ("5M3D5M", "ACGTACGTAC", 104, 106, false, Some(vec![Base(4), Deletion, Deletion])),
+            ("5M100N5M", "ACGTACGTAC", 104, 106, false, Some(vec![Base(4), Skip, Skip])),
+            ("3S5M2S", "TTTACGTAGG", 100, 102, false, Some(vec![Base(3), Base(4), Base(5)])),
+            ("4H5M", "ACGTA", 100, 101, false, Some(vec![Base(0), Base(1)])),
+            // clipped bases don't cover the span
+            ("3S5M", "TTTACGTA", 98, 100, false, None),
+        ];
+        for (cigar, sequence, start, end, trailing_insertion, expected) in cases {
+            let record = read("r1", 0, 100, cigar, sequence);
+            assert_eq!(span_bases(&record, start, end, trailing_insertion), expected, "{} {}-{}", cigar, start, end);
+        }
+    }
+
+    #[test]
+    fn classify_read_compares_the_alleles() {
+        // cigar and sequence of a read at 100, variant and expected allele
+        // and read index
+        let cases = [
+            ("10M", "ACGTACGTAC", 102, "G", "T", Some((AlleleSupport::Ref, Some(2)))),
+            ("10M", "ACTTACGTAC", 102, "G", "T", Some((AlleleSupport::Alt(0), Some(2)))),
+            ("3M2I5M", "ACGTTTACGT", 102, "G", "GTT", Some((AlleleSupport::Alt(0), Some(2)))),
+            ("3M2D5M", "ACGTACGT", 102, "GTA", "G", Some((AlleleSupport::Alt(0), Some(2)))),
+            ("3M10N5M", "ACGTACGT", 102, "GT", "G", Some((AlleleSupport::Other, Some(2)))),
+            ("2M8S", "ACGTACGTAC", 102, "G", "T", None),
+        ];
+        for (cigar, sequence, position, reference, alternate, expected) in cases {
+            let record = read("r1", 0, 100, cigar, sequence);
+            let allele = classify_read(&record, position, reference.as_bytes(), &[alternate.as_bytes().to_vec()]);
+            let found = allele.map(|allele| (allele.support, allele.read_index));
+            assert_eq!(found, expected, "{} {}>{}", cigar, reference, alternate);
+        }
+    }
+
+    #[test]
+    fn read_cycle_of_hard_clipped_reverse_reads() {
+        // flags, cigar, read index and expected cycle and read length
+        let cases = [
+            (0, "3H10M", 2, (5, 13)),
+            (16, "10M", 2, (7, 10)),
+            // the trailing hard clip was sequenced first on the reverse strand
+            (16, "10M2H", 9, (2, 12)),
+            (16, "3H10M2H", 0, (11, 15)),
+        ];
+        for (flags, cigar, read_index, expected) in cases {
+            let record = read("r1", flags, 100, cigar, "ACGTACGTAC");
+            assert_eq!(read_cycle(&record, read_index), expected, "{} {} {}", flags, cigar, read_index);
+        }
+    }
+
+    #[test]
+    fn merge_mates_keeps_supplementary_alignments_apart() {
+        // flags of the alignments of read a, expected observations
+        let cases = [
+            (vec![99, 147], vec!["99+147"]),
+            (vec![2145, 99, 147], vec!["2145", "99+147"]),
+            (vec![99, 2145, 147], vec!["99+147", "2145"]),
+        ];
+        for (flags, expected) in cases {
+            let observations: Vec<_> = flags
+                .iter()
+                .map(|&flags| (read("a", flags, 100, "5M", "ACGTA"), flags.to_string()))
+                .collect();
+            let merged: Vec<String> = merge_mates(
+                observations,
+                |(record, _)| mate_key(record),
+                |(record, first), (_, second)| (record, format!("{}+{}", first, second)),
+            )
+            .into_iter()
+            .map(|(_, label)| label)
+            .collect();
+            assert_eq!(merged, expected, "{:?}", flags);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use varianth_core::catalogue::sbs_mutation_type;
+use varianth_core::sequence::is_pyrimidine;
+
+// summary of the strand and orientation of the alternative reads by sample
+// and mutation type. damage of the dna before the library is built (8-oxoG
+// G>T, FFPE deamination C>T) shows as an excess of one read orientation,
+// the counts are collapsed to the pyrimidine strand so both strands of the
+// same damage add up in the same row.
+
+/// Alternative reads of one allele, in the strand of the vcf.
+pub struct AltReads {
+    pub forward: u32,
+    pub reverse: u32,
+    pub f1r2: u32,
+    pub f2r1: u32,
+}
+
+#[derive(Default)]
+struct SummaryCounts {
+    variants: u32,
+    forward: u32,
+    reverse: u32,
+    f1r2: u32,
+    f2r1: u32,
+}
+
+#[derive(Default)]
+pub struct ArtefactSummary {
+    rows: BTreeMap<(String, String), SummaryCounts>,
+}
+
+const SUMMARY_HEADER: &str = "sample\tmutation_type\tvariants\talt_forward\talt_reverse\talt_f1r2\talt_f2r1\tf1r2_fraction";
+
+impl ArtefactSummary {
+    /// Adds a substitution, the context is the reference sequence centered
+    /// on the variant (the MS field), or only the reference base. Anything
+    /// that is not a single base substitution is ignored.
+    pub fn add(&mut self, sample: &str, context: &[u8], alt: &[u8], reads: &AltReads) {
+        if alt.len() != 1 {
+            return;
+        }
+        let mutation_type = match sbs_mutation_type(context, alt[0]) {
+            Some(mutation_type) => mutation_type,
+            None => return,
+        };
+        // in the pyrimidine strand the strand and orientation are swapped
+        let flip = !is_pyrimidine(context[context.len() / 2].to_ascii_uppercase());
+        let (forward, reverse, f1r2, f2r1) = if flip {
+            (reads.reverse, reads.forward, reads.f2r1, reads.f1r2)
+        } else {
+            (reads.forward, reads.reverse, reads.f1r2, reads.f2r1)
+        };
+
+        let counts = self.rows.entry((sample.to_string(), mutation_type)).or_default();
+        counts.variants += 1;
+        counts.forward += forward;
+        counts.reverse += reverse;
+        counts.f1r2 += f1r2;
+        counts.f2r1 += f2r1;
+    }
+
+    pub fn write_tsv<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        writeln!(out, "{}", SUMMARY_HEADER)?;
+        for ((sample, mutation_type), counts) in &self.rows {
+            let oriented = counts.f1r2 + counts.f2r1;
+            let fraction = if oriented > 0 {
+                format!("{:.4}", counts.f1r2 as f64 / oriented as f64)
+            } else {
+                "NA".to_string()
+            };
+            writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                sample,
+                mutation_type,
+                counts.variants,
+                counts.forward,
+                counts.reverse,
+                counts.f1r2,
+                counts.f2r1,
+                fraction)?;
+        }
+        Ok(())
+    }
+}
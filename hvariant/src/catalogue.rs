@@ -0,0 +1,241 @@
+
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use noodles::vcf;
+use noodles::vcf::record::alternate_bases::Allele;
+
+use noodles::fasta::indexed_reader::Builder;
+
+use varianth_core::catalogue::{sbs_mutation_type, sbs_mutation_types, Catalogue};
+
+use crate::core::IndexedFastaReader;
+use crate::input::{read_variants, InputFormat};
+use crate::ms::get_ntp;
+use crate::samples::SampleFilter;
+
+// builds a mutation catalogue (SBS) from a vcf annotated with addms (the
+// context is read from the MS info field so the genome is not needed), or
+// from any input format with the genome.
+// vcf files with samples have one column per sample and each variant is
+// only counted in the samples that carry it, sites only vcf files are
+// counted in a single column.
+
+pub fn default_sample_name(variants: &Path) -> String {
+    let file_name = variants
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let file_name = file_name.strip_suffix(".gz").unwrap_or(&file_name);
+    let file_name = [".vcf", ".maf", ".tsv", ".txt"]
+        .iter()
+        .find_map(|suffix| file_name.strip_suffix(suffix))
+        .unwrap_or(file_name);
+    file_name.to_string()
+}
+
+// column name for a set of strata, i.e. sample|RS=leading|RT=2
+fn column_name(sample: &str, strata: &[(String, String)]) -> String {
+    let mut name = sample.to_string();
+    for (key, value) in strata {
+        name.push_str(&format!("|{}={}", key, value));
+    }
+    name
+}
+
+fn vcf_strata(record: &vcf::Record, strata_keys: &[vcf::record::info::field::Key]) -> Vec<(String, String)> {
+    strata_keys
+        .iter()
+        .map(|key| {
+            let value = match record.info().get(key) {
+                Some(Some(value)) => value.to_string(),
+                _ => "NA".to_string(),
+            };
+            (key.to_string(), value)
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct CatalogueCounts {
+    kval: Option<usize>,
+    columns: BTreeSet<String>,
+    hash_counts: HashMap<(String, String), f64>,
+    n_skipped: usize,
+}
+
+impl CatalogueCounts {
+    // counts a substitution in the given columns, anything that is not a
+    // single base substitution is skipped
+    fn add(&mut self, context: &str, ref_bases: &str, alt_bases: &str, columns: &[String], location: &str) {
+        if ref_bases.len() != 1 || alt_bases.len() != 1 {
+            self.n_skipped += 1;
+            return;
+        }
+
+        let record_kval = context.len() / 2;
+        match self.kval {
+            None => self.kval = Some(record_kval),
+            Some(k) if k != record_kval => panic!("Contexts of different sizes ({} and {})", 2 * k + 1, context.len()),
+            _ => {},
+        }
+
+        if !context.as_bytes()[record_kval].eq_ignore_ascii_case(&ref_bases.as_bytes()[0]) {
+            panic!("Context {} does not match the reference base at {}", context, location);
+        }
+
+        let mutation_type = match sbs_mutation_type(context.as_bytes(), alt_bases.as_bytes()[0]) {
+            Some(mutation_type) => mutation_type,
+            None => {
+                self.n_skipped += 1;
+                return;
+            },
+        };
+
+        for column in columns {
+            let stat = self.hash_counts.entry((mutation_type.clone(), column.clone())).or_insert(0.0);
+            *stat += 1.0;
+            self.columns.insert(column.clone());
+        }
+    }
+
+    fn into_catalogue(self) -> Catalogue {
+        if self.n_skipped > 0 {
+            eprintln!("{} alleles are not single base substitutions and were skipped", self.n_skipped);
+        }
+
+        let mut catalogue = Catalogue::new(
+            sbs_mutation_types(self.kval.unwrap_or(1)),
+            self.columns.into_iter().collect(),
+        );
+        for ((mutation_type, column), value) in self.hash_counts {
+            catalogue.add(&mutation_type, &column, value);
+        }
+        catalogue
+    }
+}
+
+/// Builds the catalogue from any input format. The context is taken from
+/// the genome if provided, otherwise from the MS field (INFO in vcf files,
+/// a column in tsv files written by addms).
+#[allow(clippy::too_many_arguments)]
+pub fn catalogue(variants_in: PathBuf, table_out: PathBuf, format: InputFormat, genome: Option<PathBuf>, kval: usize, key_name: String, strata: Vec<String>, sample_name: String, sample_filter: SampleFilter) {
+
+    let mut reference_reader = genome.map(|path| {
+        Builder::default()
+            .build_from_path(path)
+            .unwrap()
+    });
+
+    let counts = match format {
+        InputFormat::Vcf => catalogue_vcf(variants_in, reference_reader.as_mut(), kval, key_name, strata, sample_name, sample_filter),
+        _ => catalogue_table(variants_in, format, reference_reader.as_mut(), kval, key_name, strata, sample_name, sample_filter),
+    };
+
+    let catalogue = counts.into_catalogue();
+    let mut out_file = BufWriter::new(File::create(table_out).unwrap());
+    catalogue.write_tsv(&mut out_file).unwrap();
+}
+
+#[allow(clippy::too_many_arguments)]
+fn catalogue_vcf(variants_in: PathBuf, mut reference_reader: Option<&mut IndexedFastaReader>, kval: usize, key_name: String, strata: Vec<String>, sample_name: String, sample_filter: SampleFilter) -> CatalogueCounts {
+
+    let mut variants_reader = vcf::reader::Builder
+        .build_from_path(variants_in)
+        .unwrap();
+    let header = variants_reader.read_header().unwrap();
+    let sample_indices = sample_filter.sample_indices(&header);
+    if sample_filter.is_active() && sample_indices.is_empty() {
+        panic!("The vcf has no samples, sample options can't be used");
+    }
+
+    let ms_key: vcf::record::info::field::Key = key_name.parse().unwrap();
+    let strata_keys: Vec<vcf::record::info::field::Key> = strata
+        .iter()
+        .map(|x| match x.parse() {
+            Ok(key) => key,
+            Err(error) => panic!("Problem parsing the info key {}: {:?}", x, error),
+        })
+        .collect();
+
+    let mut counts = CatalogueCounts::default();
+    // samples without variants are still reported when there are no strata
+    if strata_keys.is_empty() {
+        counts.columns.extend(sample_indices.iter().map(|(_, name)| name.clone()));
+    }
+
+    for result in variants_reader.records(&header) {
+        let record = result.unwrap();
+        let chrom = record.chromosome().to_string();
+        let location = format!("{}:{}", chrom, record.position());
+
+        let context = match reference_reader.as_deref_mut() {
+            Some(reference) => get_ntp(&chrom, usize::from(record.position()), reference, kval),
+            None => match record.info().get(&ms_key) {
+                Some(Some(vcf::record::info::field::Value::String(context))) => context.clone(),
+                _ => panic!("Record at {} has no {} field, run addms first or use --genome", location, ms_key),
+            },
+        };
+
+        let record_strata = vcf_strata(&record, &strata_keys);
+        let ref_bases = record.reference_bases().to_string();
+        for (allele_idx, allele) in record.alternate_bases().iter().enumerate() {
+            let alt_bases = match allele {
+                Allele::Bases(bases) => bases.iter().map(|x| char::from(*x)).collect(),
+                _ => String::new(),
+            };
+
+            let carriers = if sample_indices.is_empty() {
+                vec![sample_name.as_str()]
+            } else {
+                sample_filter.carriers(&record, &sample_indices, allele_idx + 1)
+            };
+            let columns: Vec<String> = carriers
+                .into_iter()
+                .map(|carrier| column_name(carrier, &record_strata))
+                .collect();
+
+            counts.add(&context, &ref_bases, &alt_bases, &columns, &location);
+        }
+    }
+    counts
+}
+
+#[allow(clippy::too_many_arguments)]
+fn catalogue_table(variants_in: PathBuf, format: InputFormat, mut reference_reader: Option<&mut IndexedFastaReader>, kval: usize, key_name: String, strata: Vec<String>, sample_name: String, sample_filter: SampleFilter) -> CatalogueCounts {
+
+    let variants = read_variants(variants_in, format, reference_reader.as_deref_mut());
+
+    let mut counts = CatalogueCounts::default();
+    if let (Some(samples), true) = (&sample_filter.samples, strata.is_empty()) {
+        counts.columns.extend(samples.iter().cloned());
+    }
+
+    for variant in variants {
+        if !sample_filter.passes_variant(&variant) {
+            continue;
+        }
+        let location = format!("{}:{}", variant.chrom, variant.position);
+
+        let context = match reference_reader.as_deref_mut() {
+            Some(reference) => get_ntp(&variant.chrom, variant.position, reference, kval),
+            None => match variant.field(&key_name) {
+                Some(context) => context.to_string(),
+                None => panic!("Variant at {} has no {} column, run addms first or use --genome", location, key_name),
+            },
+        };
+
+        let variant_strata: Vec<(String, String)> = strata
+            .iter()
+            .map(|key| (key.clone(), variant.field(key).unwrap_or("NA").to_string()))
+            .collect();
+        let sample = variant.sample.as_deref().unwrap_or(&sample_name);
+        let columns = vec![column_name(sample, &variant_strata)];
+
+        counts.add(&context, &variant.reference, &variant.alternate, &columns, &location);
+    }
+    counts
+}
@@ -3,6 +3,72 @@
 use noodles::core;
 use noodles::vcf;
 use noodles::bed;
+use noodles::fasta;
+
+pub type IndexedFastaReader = fasta::IndexedReader<Box<dyn fasta::io::BufReadSeek>>;
+
+/// Bases of the reference kept in memory by ReferenceCache.
+pub const REFERENCE_WINDOW: usize = 100_000;
+
+/// Indexed fasta reader that keeps a window of the reference in memory, so
+/// sorted queries read the fasta once per window instead of once per query.
+pub struct ReferenceCache {
+    reader: IndexedFastaReader,
+    window_size: usize,
+    chrom: String,
+    // 1-based position of the first base in the window
+    start: usize,
+    sequence: Vec<u8>,
+}
+
+impl ReferenceCache {
+    pub fn new(reader: IndexedFastaReader, window_size: usize) -> Self {
+        ReferenceCache {
+            reader,
+            window_size,
+            chrom: String::new(),
+            start: 0,
+            sequence: Vec::new(),
+        }
+    }
+
+    /// Bases from `start` to `end` (1-based, inclusive).
+    pub fn sequence(&mut self, chrom: &str, start: usize, end: usize) -> &[u8] {
+        let window_end = self.start + self.sequence.len();
+        if chrom != self.chrom || start < self.start || end >= window_end {
+            self.load(chrom, start, end);
+        }
+        &self.sequence[start - self.start..=end - self.start]
+    }
+
+    fn load(&mut self, chrom: &str, start: usize, end: usize) {
+        // the window is clamped to the contig, a query past the end fails
+        // as it does without the cache
+        let length = self
+            .reader
+            .index()
+            .iter()
+            .find(|x| x.name() == chrom)
+            .map(|x| x.length() as usize);
+        let window_end = match length {
+            Some(length) => (start + self.window_size - 1).min(length).max(end),
+            None => end,
+        };
+
+        let region = core::Region::new(
+            chrom,
+            core::Position::try_from(start).unwrap()..=core::Position::try_from(window_end).unwrap(),
+        );
+        let record = match self.reader.query(&region) {
+            Ok(record) => record,
+            Err(error) => panic!("Problem reading the region {} from the fasta: {:?}", region, error),
+        };
+
+        self.chrom = chrom.to_string();
+        self.start = start;
+        self.sequence = record.sequence().as_ref().to_vec();
+    }
+}
 
 pub fn fromvcfrecord2region(vcf_record: &vcf::Record) -> core::Region {
     let pos1 = core::Position::try_from(usize::from(
@@ -11,17 +77,14 @@ pub fn fromvcfrecord2region(vcf_record: &vcf::Record) -> core::Region {
     .unwrap();
 
     let chrom = vcf_record.chromosome().to_string();
-    let region = core::Region::new(chrom, pos1..=pos1);
-
-    region
+    core::Region::new(chrom, pos1..=pos1)
 }
 
 pub fn bed_record_to_region(record: bed::Record<3>) -> core::Region {
     let chr_name = record.reference_sequence_name();
     let pos1 = record.start_position();
     let pos2 = record.end_position();
-    let region = core::Region::new(chr_name, pos1..=pos2);
-    region
+    core::Region::new(chr_name, pos1..=pos2)
 }
 
 /* 
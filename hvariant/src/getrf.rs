@@ -1,33 +1,59 @@
 
 
-use crate::core::bed_record_to_region;
+use crate::alignment::{mate_key, merge_mates, span_bases, SpanBase};
+use crate::core::{bed_record_to_region, ReferenceCache, REFERENCE_WINDOW};
+use crate::parallel::process_chunks;
+use crate::reads::{AlignmentQuery, ReadsInput, SampleReads};
+use crate::readfilter::{FilterCounts, ReadFilter};
+use crate::stats::mean;
+use crate::umi::{consensus, FamilyCounts, UmiFamilies};
 
 use std::ops::Bound;
-use std::path::PathBuf;
-use std::fs::File;
-use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
 
 use std::collections::HashMap;
 
 use noodles::core;
-use noodles::sam;
-use noodles::bam;
 use noodles::bed;
-use noodles::sam::record::cigar::Cigar;
-use noodles::sam::record::cigar::op::kind::Kind;
+use noodles::fasta;
+use noodles::sam;
+
+use varianth_core::codon::GeneticCode;
+use varianth_core::sequence::complement;
 
 use std::io::Write;
-use std::fs::OpenOptions;
 
 
-pub fn readfreq(reads: PathBuf, sites_in: PathBuf, tsv_out: PathBuf) -> () {
+// regions given to each thread
+const REGIONS_PER_CHUNK: usize = 1000;
+
+/// Counts the read sequences of each interval of the bed file. With the
+/// reference genome (`reads.reference`) they are compared to the reference
+/// sequence. With a genetic code the intervals are codons (bed6, with
+/// strand) and the sequences are translated, this needs the reference.
+/// `counting` sets how reads are counted (fragments and UMI families).
+pub fn readfreq(
+    reads: ReadsInput,
+    sites_in: PathBuf,
+    tsv_out: PathBuf,
+    read_filter: ReadFilter,
+    threads: usize,
+    genetic_code: Option<GeneticCode>,
+    counting: ReadCounting,
+) {
+    if genetic_code.is_some() && reads.reference.is_none() {
+        panic!("Problem with the codons: the reference codon needs the reference genome (--reference)");
+    }
 
-    // a block to define the output file
-    let mut out_file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open(tsv_out)
-        .unwrap();
+    // the table is written to a temporary file that replaces the output at
+    // the end, so a failed run doesn't leave a partial table
+    let tmp_out = temporary_path(&tsv_out);
+    let mut out_file = BufWriter::new(File::create(&tmp_out).unwrap_or_else(|e| {
+        panic!("Problem creating the output file {:?}: {:?}", tmp_out, e)
+    }));
+    writeln!(out_file, "{}", table_header(&counting, reads.reference.is_some(), genetic_code.is_some())).unwrap();
 
     let sites_in_file_result = File::open(sites_in);
     let sites_in_file = match sites_in_file_result {
@@ -37,51 +63,175 @@ pub fn readfreq(reads: PathBuf, sites_in: PathBuf, tsv_out: PathBuf) -> () {
 
     let mut variants_reader = bed::Reader::new(sites_in_file);
 
-    // a block to define the bam reader
-    let bam_path = reads;
-    let mut bam_reader: bam::IndexedReader<noodles::bgzf::Reader<std::fs::File>> = bam::indexed_reader::Builder::default()
-        .build_from_path(bam_path).unwrap();
-    let bam_header: sam::Header = bam_reader.read_header().unwrap();
-
-    for bed_record_result in variants_reader.records::<3>() {
-        let bed_record = match bed_record_result {
-            Ok(bed_record) => bed_record,
-            Err(error) => panic!("Problem reading the file: {:?}", error),
-        };
+    // codons need the strand of the gene, from a bed6 file
+    let mut intervals: Vec<Interval> = Vec::new();
+    if genetic_code.is_some() {
+        for bed_record_result in variants_reader.records::<6>() {
+            let bed_record = match bed_record_result {
+                Ok(bed_record) => bed_record,
+                Err(error) => panic!("Problem reading the file (codons need a bed6 file with strand): {:?}", error),
+            };
+            let region = core::Region::new(
+                bed_record.reference_sequence_name(),
+                bed_record.start_position()..=bed_record.end_position(),
+            );
+            intervals.push(Interval { region, strand: bed_record.strand() });
+        }
+    } else {
+        for bed_record_result in variants_reader.records::<3>() {
+            let bed_record = match bed_record_result {
+                Ok(bed_record) => bed_record,
+                Err(error) => panic!("Problem reading the file: {:?}", error),
+            };
+            intervals.push(Interval { region: bed_record_to_region(bed_record), strand: None });
+        }
+    }
 
-        let region = bed_record_to_region(bed_record);
-
-        let hash_freq = get_readfrequency(
-            &region,
-            &mut bam_reader,
-            &bam_header,
-        );
-
-        let region_seqname = region.name();
-        let region_start_bpos = region.start();
-        let region_start_pos = get_position(region_start_bpos).unwrap();
-        let region_start_usize = usize::from(region_start_pos);
-        let region_start_usize_0base = region_start_usize.checked_sub(1).unwrap();
-        let region_start_string = region_start_usize_0base.to_string();
-        
-        let region_end_bpos = region.end();
-        let region_end_pos = get_position(region_end_bpos).unwrap();
-        let region_end_string = usize::from(region_end_pos).to_string();
-
-        for (key, value) in &hash_freq {
-            let output = format!("{}\t{}\t{}\t{}\t{}",
-                region_seqname,
-                region_start_string,
-                region_end_string,
-                key,
-                value);
-            //println!("{}", output);
-            writeln!(out_file, "{}", output).unwrap();
+    // the table is sorted by contig (in the order of the reads header, then
+    // the contigs missing from it in the order of the bed file), start and
+    // end, the intervals are given to the threads in chunks and written back
+    // in order
+    let mut contig_order: HashMap<String, usize> = reads
+        .contigs()
+        .into_iter()
+        .enumerate()
+        .map(|(idx, name)| (name, idx))
+        .collect();
+    for interval in &intervals {
+        let next = contig_order.len();
+        contig_order.entry(interval.region.name().to_string()).or_insert(next);
+    }
+    intervals.sort_by_key(|interval| {
+        let bounds = interval.region.interval();
+        (contig_order[interval.region.name()], bounds.start(), bounds.end())
+    });
+    let mut chunks: Vec<Vec<Interval>> = Vec::new();
+    for interval in intervals {
+        match chunks.last_mut() {
+            Some(chunk) if chunk.len() < REGIONS_PER_CHUNK => chunk.push(interval),
+            _ => chunks.push(vec![interval]),
         }
+    }
 
-        //println!("{:?}", hash_freq);
+    let totals = process_chunks(
+        chunks,
+        threads,
+        // bam, cram or sam readers, one for each sample
+        || (ReadfreqReaders::open(&reads), ReadfreqCounts::default()),
+        |readers, counts, chunk| {
+            chunk
+                .iter()
+                .map(|interval| {
+                    let region = &interval.region;
+                    let reference_sequence = readers.reference.as_mut().map(|reference| {
+                        let start = usize::from(get_position(region.start()).unwrap());
+                        let end = usize::from(get_position(region.end()).unwrap());
+                        reference.sequence(region.name(), start, end).to_ascii_uppercase()
+                    });
+                    let frequencies = readers
+                        .samples
+                        .iter_mut()
+                        .map(|sample| {
+                            let hash_freq = get_readfrequency(
+                                region,
+                                sample.reader.as_mut(),
+                                &read_filter,
+                                &counting,
+                                counts,
+                            );
+                            (sample.label.clone(), hash_freq)
+                        })
+                        .collect::<Vec<_>>();
+                    (reference_sequence, frequencies)
+                })
+                .collect::<Vec<_>>()
+        },
+        |chunk, frequencies| {
+            for (interval, (reference_sequence, region_frequencies)) in chunk.iter().zip(frequencies) {
+                let region = &interval.region;
+                let region_seqname = region.name();
+                let region_start_bpos = region.start();
+                let region_start_pos = get_position(region_start_bpos).unwrap();
+                let region_start_usize = usize::from(region_start_pos);
+                let region_start_usize_0base = region_start_usize.checked_sub(1).unwrap();
+                let region_start_string = region_start_usize_0base.to_string();
+
+                let region_end_bpos = region.end();
+                let region_end_pos = get_position(region_end_bpos).unwrap();
+                let region_end_string = usize::from(region_end_pos).to_string();
+
+                // the reference codon is the same for every haplotype
+                let reference_codon = match (genetic_code, &reference_sequence) {
+                    (Some(genetic_code), Some(reference_sequence)) => {
+                        let codon = coding_strand(reference_sequence, interval.strand);
+                        let amino_acid = format_amino_acid(genetic_code.translate(&codon));
+                        Some((String::from_utf8_lossy(&codon).to_string(), amino_acid))
+                    },
+                    _ => None,
+                };
+
+                for (label, hash_freq) in region_frequencies {
+                    let depth: usize = hash_freq.values().map(|x| x.reads).sum();
+                    // most common sequences first, ties by sequence
+                    let mut haplotypes: Vec<(&String, &HaplotypeCounts)> = hash_freq.iter().collect();
+                    haplotypes.sort_by(|a, b| b.1.reads.cmp(&a.1.reads).then(b.1.families.cmp(&a.1.families)).then(a.0.cmp(b.0)));
+                    for (key, value) in haplotypes {
+                        let mut output = format!("{}\t{}\t{}\t{}\t{}\t{}",
+                            region_seqname,
+                            region_start_string,
+                            region_end_string,
+                            label,
+                            key,
+                            value.reads);
+                        if counting.umi.is_some() {
+                            output.push_str(&format!("\t{}", value.families));
+                        }
+                        if counting.fragments {
+                            output.push_str(&format!("\t{}", value.discordant));
+                        }
+                        let frequency = if depth > 0 { value.reads as f64 / depth as f64 } else { 0.0 };
+                        output.push_str(&format!("\t{}\t{:.4}", depth, frequency));
+                        if let Some(reference_sequence) = &reference_sequence {
+                            let differences = haplotype_differences(key, reference_sequence, region_start_usize);
+                            output.push_str(&format!("\t{}\t{}\t{}",
+                                String::from_utf8_lossy(reference_sequence),
+                                format_positions(&differences),
+                                u8::from(differences.is_empty())));
+                        }
+                        if let (Some(genetic_code), Some((ref_codon, ref_amino_acid))) = (genetic_code, &reference_codon) {
+                            let codon = coding_strand(key.as_bytes(), interval.strand);
+                            output.push_str(&format!("\t{}\t{}\t{}\t{}\t{}",
+                                format_strand(interval.strand),
+                                String::from_utf8_lossy(&codon),
+                                format_amino_acid(genetic_code.translate(&codon)),
+                                ref_codon,
+                                ref_amino_acid));
+                        }
+                        writeln!(out_file, "{}", output).unwrap();
+                    }
+                }
+            }
+        },
+    );
+
+    out_file.flush().unwrap();
+    drop(out_file);
+    if let Err(error) = fs::rename(&tmp_out, &tsv_out) {
+        panic!("Problem writing the output file {:?}: {:?}", tsv_out, error);
+    }
 
-    } 
+    let mut counts = ReadfreqCounts::default();
+    for worker_counts in totals {
+        counts.merge(worker_counts);
+    }
+    counts.filter.report();
+    counts.dropped.report();
+    if counting.fragments {
+        counts.pairs.report();
+    }
+    if counting.umi.is_some() {
+        counts.families.report();
+    }
 
     /*
     let mut records = variants_reader.records::<3>()
@@ -94,6 +244,112 @@ pub fn readfreq(reads: PathBuf, sites_in: PathBuf, tsv_out: PathBuf) -> () {
 
 }
 
+// columns of the table, the reference and codon columns are optional
+fn table_header(counting: &ReadCounting, reference: bool, codons: bool) -> String {
+    let mut columns = vec!["chrom", "start", "end", "sample", "sequence", "reads"];
+    if counting.umi.is_some() {
+        columns.push("families");
+    }
+    if counting.fragments {
+        columns.push("discordant");
+    }
+    columns.extend(["depth", "frequency"]);
+    if reference {
+        columns.extend(["reference", "mismatches", "reference_haplotype"]);
+    }
+    if codons {
+        columns.extend(["strand", "codon", "amino_acid", "reference_codon", "reference_amino_acid"]);
+    }
+    columns.join("\t")
+}
+
+// output.tsv is written as output.tsv.tmp in the same directory
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
+
+// an interval of the bed file, codons have the strand of the gene
+struct Interval {
+    region: core::Region,
+    strand: Option<bed::record::Strand>,
+}
+
+// readers of each thread, the reference genome is optional
+struct ReadfreqReaders {
+    samples: Vec<SampleReads>,
+    reference: Option<ReferenceCache>,
+}
+
+impl ReadfreqReaders {
+    fn open(reads: &ReadsInput) -> Self {
+        let reference = reads.reference.as_ref().map(|path| {
+            let reader = fasta::indexed_reader::Builder::default()
+                .build_from_path(path)
+                .unwrap();
+            ReferenceCache::new(reader, REFERENCE_WINDOW)
+        });
+        ReadfreqReaders { samples: reads.open(), reference }
+    }
+}
+
+// genomic positions (1-based) where the sequence of the reads differs from
+// the reference, insertions are given by the position of the base before
+fn haplotype_differences(sequence: &str, reference: &[u8], start: usize) -> Vec<usize> {
+    let mut differences: Vec<usize> = Vec::new();
+    let mut offset = 0;
+    for base in sequence.bytes() {
+        if base.is_ascii_lowercase() {
+            let position = start + offset - 1;
+            if differences.last() != Some(&position) {
+                differences.push(position);
+            }
+            continue;
+        }
+        if reference.get(offset) != Some(&base) {
+            differences.push(start + offset);
+        }
+        offset += 1;
+    }
+    differences
+}
+
+fn format_positions(positions: &[usize]) -> String {
+    if positions.is_empty() {
+        return ".".to_string();
+    }
+    positions.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(",")
+}
+
+// sequence in the strand of the gene, deletions stay as "-"
+fn coding_strand(sequence: &[u8], strand: Option<bed::record::Strand>) -> Vec<u8> {
+    match strand {
+        Some(bed::record::Strand::Reverse) => sequence
+            .iter()
+            .rev()
+            .map(|&base| if base == b'-' { base } else { complement(base) })
+            .collect(),
+        _ => sequence.to_vec(),
+    }
+}
+
+fn format_strand(strand: Option<bed::record::Strand>) -> &'static str {
+    match strand {
+        Some(bed::record::Strand::Forward) => "+",
+        Some(bed::record::Strand::Reverse) => "-",
+        None => ".",
+    }
+}
+
+// sequences that are not a codon (indels) can't be translated
+fn format_amino_acid(amino_acid: Option<u8>) -> String {
+    match amino_acid {
+        Some(amino_acid) => char::from(amino_acid).to_string(),
+        None => "NA".to_string(),
+    }
+}
+
 fn get_position(bound: Bound<core::Position>) -> Option<core::Position>  {
     let position_result: Option<core::Position> = match bound {
         Bound::Included(position) => Some(position),
@@ -103,163 +359,258 @@ fn get_position(bound: Bound<core::Position>) -> Option<core::Position>  {
     position_result
 }
 
-fn get_readfrequency(
+/// How the reads of an interval are counted.
+pub struct ReadCounting {
+    /// overlapping mates of a pair count once
+    pub fragments: bool,
+    /// reads of the same molecule are also counted as one family
+    pub umi: Option<UmiFamilies>,
+}
+
+// reads and families with a sequence in an interval, and the fragments
+// whose mates had different sequences
+#[derive(Default)]
+struct HaplotypeCounts {
+    reads: usize,
+    families: usize,
+    discordant: usize,
+}
+
+// totals of each thread
+#[derive(Default)]
+struct ReadfreqCounts {
+    filter: FilterCounts,
+    dropped: DropCounts,
+    pairs: PairCounts,
+    families: FamilyCounts,
+}
+
+impl ReadfreqCounts {
+    fn merge(&mut self, other: ReadfreqCounts) {
+        self.filter.merge(other.filter);
+        self.dropped.merge(other.dropped);
+        self.pairs.merge(other.pairs);
+        self.families.merge(other.families);
+    }
+}
+
+/// Reads that pass the filters but can't be used for an interval.
+#[derive(Default)]
+struct DropCounts {
+    /// without alignment position
+    unaligned: u64,
+    /// without sequence (SEQ is *)
+    missing_sequence: u64,
+    /// starting or ending within the interval
+    partial: u64,
+}
+
+impl DropCounts {
+    fn merge(&mut self, other: DropCounts) {
+        self.unaligned += other.unaligned;
+        self.missing_sequence += other.missing_sequence;
+        self.partial += other.partial;
+    }
+
+    fn report(&self) {
+        eprintln!("Reads dropped without alignment position: {}", self.unaligned);
+        eprintln!("Reads dropped without sequence: {}", self.missing_sequence);
+        eprintln!("Reads dropped not covering the whole interval: {}", self.partial);
+    }
+}
+
+fn get_readfrequency<R: AlignmentQuery + ?Sized>(
     region_in: &core::Region,
-    bam_reader: &mut bam::IndexedReader<noodles::bgzf::Reader<std::fs::File>>,
-    bam_header: &sam::Header,
-) -> HashMap<String, usize> {
+    reads_reader: &mut R,
+    read_filter: &ReadFilter,
+    counting: &ReadCounting,
+    counts: &mut ReadfreqCounts,
+) -> HashMap<String, HaplotypeCounts> {
+    let ReadfreqCounts { filter: filter_counts, dropped: drop_counts, pairs: pair_counts, families: family_counts } = counts;
 
     let region_start_usize = usize::from(get_position(region_in.start()).unwrap());
 
     let region_end_usize = usize::from(get_position(region_in.end()).unwrap());
-    let region_end_usize_plus1_result = region_end_usize.checked_add(1); // this is for 0-based range
-    let region_end_usize_plus1 = match region_end_usize_plus1_result {
-        Some(region_end_usize_plus1) => region_end_usize_plus1,
-        None => panic!("Problem with usize subtraction, negative position?"),
-    };
-    let query = bam_reader.query(bam_header, &region_in).unwrap();
-    let mut hash_read_counts: HashMap<String, usize> = HashMap::new();
+    let query = reads_reader.query(region_in).unwrap();
+    let mut observations: Vec<ReadHaplotype> = Vec::new();
     for alig_result in query {
         let alig = alig_result.unwrap();
-        let record_flags = alig.flags();
+        if !read_filter.passes_read(&alig, filter_counts) {
+            continue;
+        }
+
+        if alig.alignment_start().is_none() || alig.alignment_end().is_none() {
+            drop_counts.unaligned += 1;
+            continue;
+        }
 
-        if record_flags.is_qc_fail() || record_flags.is_duplicate() || record_flags.is_secondary() || record_flags.is_supplementary() {
+        // secondary and supplementary reads can be stored without sequence
+        if alig.sequence().is_empty() {
+            drop_counts.missing_sequence += 1;
             continue;
         }
 
-        let alig_start = alig.alignment_start().unwrap();
-        let alig_start_usize = usize::from(alig_start);
-        let alig_end =  alig.alignment_end().unwrap();
-        let alig_end_usize = usize::from(alig_end);
-
-        let alig_sequence = alig.sequence().to_string();
-        let alig_cigar = alig.cigar();
-        println!("{:?}", alig_cigar);
-
-        let sel_seq_result = get_seq(
-            &alig_sequence,
-            &alig_start_usize,
-            &alig_end_usize,
-            &region_start_usize,
-            &region_end_usize_plus1,
-            alig_cigar,
-        );
-
-        let sel_seq = match sel_seq_result {
-            Some(sel_seq) => sel_seq,
-            None => continue,
+        let span = match span_bases(&alig, region_start_usize, region_end_usize, false) {
+            Some(span) => span,
+            None => {
+                drop_counts.partial += 1;
+                continue;
+            },
         };
-        println!("seq: {}, pos:{} ", sel_seq, region_end_usize);
-        let stat = hash_read_counts.entry(sel_seq).or_insert(0);
-        *stat += 1;
-    }
-    hash_read_counts
-}
 
+        // base qualities of the read bases aligned to the region
+        let read_indices: Vec<usize> = span
+            .iter()
+            .filter_map(|x| match x {
+                SpanBase::Base(idx) | SpanBase::Inserted(idx) => Some(*idx),
+                _ => None,
+            })
+            .collect();
+        if !read_filter.passes_bases(&alig, &read_indices, filter_counts) {
+            continue;
+        }
+        let family = counting.umi.as_ref().and_then(|umi| umi.family(&alig));
+        observations.push(ReadHaplotype::new(&alig, &span, family));
+    }
 
-/*
+    let observations = if counting.fragments {
+        merge_mates(
+            observations,
+            |haplotype| haplotype.mate.clone(),
+            |first, second| first.merge(second, pair_counts),
+        )
+    } else {
+        observations
+    };
+    let mut hash_read_counts: HashMap<String, HaplotypeCounts> = HashMap::new();
+    for haplotype in &observations {
+        let counts = hash_read_counts.entry(haplotype.sequence.clone()).or_default();
+        counts.reads += 1;
+        counts.discordant += usize::from(haplotype.discordant);
+    }
 
-pub enum Kind {
-    Match,
-    Insertion,
-    Deletion,
-    Skip,
-    SoftClip,
-    HardClip,
-    Pad,
-    SequenceMatch,
-    SequenceMismatch,
+    // consensus of each family, in the order of their first read
+    if let Some(umi) = &counting.umi {
+        let mut families: Vec<(&str, Vec<&str>)> = Vec::new();
+        let mut family_indices: HashMap<&str, usize> = HashMap::new();
+        for haplotype in &observations {
+            let family = match &haplotype.family {
+                Some(family) => family.as_str(),
+                None => {
+                    family_counts.reads_without_umi += 1;
+                    continue;
+                },
+            };
+            let idx = *family_indices.entry(family).or_insert_with(|| {
+                families.push((family, Vec::new()));
+                families.len() - 1
+            });
+            families[idx].1.push(&haplotype.sequence);
+        }
+        for (_, sequences) in families {
+            if sequences.len() < umi.min_family_size {
+                family_counts.small_families += 1;
+                continue;
+            }
+            family_counts.families += 1;
+            hash_read_counts.entry(consensus(&sequences)).or_default().families += 1;
+        }
+    }
+    hash_read_counts
 }
 
-From what I can gather,
-
-- M or Match/Mismatch in the read should do nothing.
-- SoftClip in the read should do nothing. ?? VERIFY!
-- SequenceMatch & SequenceMismatch (these are the new M, = & X)
-
-- Deletion (in the read) should decrease the positions of the region.
-- Skip regions (in the read) should decrease the positions of the region.
-        note that these aren't going to be common in WGS data but yes in RNA
-- HardClip (in the read) should decrease the positions of the region. ?? VERIFY!
-
-- Insertion (in the read) should increase the positions of the region.
-*/
 
+// sequence of a read over the interval, aligned to the reference: one
+// character by reference position (bases in uppercase, "-" for deletions and
+// "N" for skips), plus the inserted bases in lowercase where they are.
+// hard clips, soft clips and pads are not part of it.
+struct ReadHaplotype {
+    sequence: String,
+    // base quality of each character, None for deletions and skips
+    qualities: Vec<Option<u8>>,
+    mate: Option<(Vec<u8>, bool)>,
+    // umi family (see UmiFamilies::family)
+    family: Option<String>,
+    // merged from mates with different sequences
+    discordant: bool,
+}
 
-fn get_seq(sequence: &str, astart: &usize, aend: &usize, rstart: &usize, rend: &usize, alig_cigar: &Cigar) -> Option<String> {
-    if rstart < astart {
-        return None;
+impl ReadHaplotype {
+    fn new(record: &sam::alignment::Record, span: &[SpanBase], family: Option<String>) -> Self {
+        let sequence = record.sequence().as_ref();
+        let quality = |idx: usize| record.quality_scores().as_ref().get(idx).map(|x| u8::from(*x));
+        let (sequence, qualities) = span
+            .iter()
+            .map(|x| match x {
+                SpanBase::Base(idx) => (char::from(u8::from(sequence[*idx]).to_ascii_uppercase()), quality(*idx)),
+                SpanBase::Inserted(idx) => (char::from(u8::from(sequence[*idx]).to_ascii_lowercase()), quality(*idx)),
+                SpanBase::Deletion => ('-', None),
+                SpanBase::Skip => ('N', None),
+            })
+            .unzip();
+        ReadHaplotype { sequence, qualities, mate: mate_key(record), family, discordant: false }
     }
 
-    if rend > aend {
-        return None;
+    fn mean_quality(&self) -> f64 {
+        let qualities: Vec<u8> = self.qualities.iter().flatten().copied().collect();
+        mean(&qualities).unwrap_or(0.0)
     }
 
-    let mut rel_start = rstart - astart + 1;
-    let mut rel_end = rend - astart + 1;
-
-    /*
-    let mut mod_rel_start = rel_start;
-    let mut mod_rel_end = rel_end;
-     */
-    let mut del_pos_modifier: usize = 0;
-    let mut softclip_pos_modifier: usize = 0;
-    let mut cursor : usize = 1;
-    let mut new_sequence = "".to_string();
-    for i in 0..alig_cigar.len() {
-        let cigar_op = alig_cigar[i]; // operation
-        let chunk_size: usize = cigar_op.len(); // block size
-        let chunk_type = cigar_op.kind();
-        match chunk_type {
-            Kind::Match | Kind::SequenceMatch | Kind::SequenceMismatch => {
-                let idx_seq = cursor - 1 - del_pos_modifier + softclip_pos_modifier;
-                let idx_seq_end = cursor + chunk_size - 1 - del_pos_modifier + softclip_pos_modifier;
-                let extra_seq = sequence[idx_seq..idx_seq_end].to_string();
-                new_sequence.push_str(&extra_seq);
-                cursor += chunk_size;
-            },
-            Kind::SoftClip => {
-                softclip_pos_modifier += chunk_size;
-            },
-            Kind::HardClip | Kind::Pad  => {
-                return None;
-            },
-            Kind::Insertion => {
-                let idx_seq = cursor - 1 - del_pos_modifier + softclip_pos_modifier;
-                let idx_seq_end = cursor + chunk_size - 1 - del_pos_modifier + softclip_pos_modifier;
-                let extra_seq = sequence[idx_seq..idx_seq_end].to_string();
-                new_sequence.push_str(&extra_seq);
-                // cursor does not move, but relative positions do
-                if rel_start > cursor {
-                    rel_start += chunk_size;
-                }
-                if rel_end > cursor {
-                    rel_end += chunk_size;
+    // overlapping mates: base by base the one with the higher quality (N if
+    // both are equal), or the whole mate with the higher mean quality when
+    // the indels are not the same
+    fn merge(self, other: ReadHaplotype, pair_counts: &mut PairCounts) -> ReadHaplotype {
+        pair_counts.merged += 1;
+        let discordant = self.sequence != other.sequence;
+        if discordant {
+            pair_counts.discordant += 1;
+        }
+        let first: Vec<char> = self.sequence.chars().collect();
+        let second: Vec<char> = other.sequence.chars().collect();
+        let same_indels = first.len() == second.len()
+            && first.iter().zip(&second).zip(self.qualities.iter().zip(&other.qualities)).all(|((a, b), (qa, qb))| {
+                a == b || (qa.is_some() && qb.is_some() && a.is_lowercase() == b.is_lowercase())
+            });
+        if !same_indels {
+            let kept = if other.mean_quality() > self.mean_quality() { other } else { self };
+            return ReadHaplotype { discordant, ..kept };
+        }
+        let (sequence, qualities) = first
+            .iter()
+            .zip(&second)
+            .zip(self.qualities.iter().zip(&other.qualities))
+            .map(|((&a, &b), (&qa, &qb))| {
+                if a == b || qa > qb {
+                    (a, qa.max(qb))
+                } else if qb > qa {
+                    (b, qb)
+                } else if a.is_lowercase() {
+                    ('n', qa)
+                } else {
+                    ('N', qa)
                 }
-            },
-            Kind::Deletion => {
-                // here adds "-"
-                let deletion_seq = "-".repeat(chunk_size);
-                new_sequence.push_str(&deletion_seq);
-                // cursor advances
-                cursor += chunk_size;
-                del_pos_modifier += chunk_size;
-            },
-            Kind::Skip => {
-                // here adds "N"
-                let skip_seq = "N".repeat(chunk_size);
-                new_sequence.push_str(&skip_seq);
-                // cursor advances
-                cursor += chunk_size;
-                del_pos_modifier += chunk_size;
-            },
-        };
+            })
+            .unzip();
+        ReadHaplotype { sequence, qualities, mate: self.mate, family: self.family, discordant }
     }
-    // this is only to convert 1-based input to 0-based substring
-    let rel_start_idx: usize = rel_start - 1;
-    let rel_end_idx: usize = rel_end - 1;
-    println!("{}",new_sequence);
-    let result_seq = &new_sequence[rel_start_idx..rel_end_idx];
-    Some(result_seq.to_string())
 }
 
+/// Overlapping mates of a pair merged into one fragment.
+#[derive(Default)]
+struct PairCounts {
+    merged: u64,
+    /// mates with different sequences
+    discordant: u64,
+}
+
+impl PairCounts {
+    fn merge(&mut self, other: PairCounts) {
+        self.merged += other.merged;
+        self.discordant += other.discordant;
+    }
+
+    fn report(&self) {
+        eprintln!("Overlapping read pairs merged: {}", self.merged);
+        eprintln!("Overlapping read pairs with discordant sequences: {}", self.discordant);
+    }
+}
@@ -0,0 +1,338 @@
+
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use noodles::core;
+
+use crate::core::IndexedFastaReader;
+
+// common representation for variants that do not come from a vcf file.
+// positions and alleles follow the vcf conventions (1-based, indels
+// anchored to the previous base), MAF "-" alleles are converted when the
+// reference genome is available.
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum InputFormat {
+    Vcf,
+    /// Mutation Annotation Format
+    Maf,
+    /// chrom, pos, ref, alt and sample columns (tab separated)
+    Tsv,
+}
+
+impl InputFormat {
+    /// Guesses the format from the file extension, vcf by default.
+    pub fn from_path(path: &Path) -> Self {
+        let file_name = path
+            .file_name()
+            .map(|x| x.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let file_name = file_name.strip_suffix(".gz").unwrap_or(&file_name);
+        if file_name.ends_with(".maf") {
+            InputFormat::Maf
+        } else if file_name.ends_with(".tsv") || file_name.ends_with(".txt") {
+            InputFormat::Tsv
+        } else {
+            InputFormat::Vcf
+        }
+    }
+}
+
+pub struct Variant {
+    pub chrom: String,
+    pub position: usize,
+    pub reference: String,
+    pub alternate: String,
+    pub sample: Option<String>,
+    /// tumour depth and alternative allele depth (MAF t_depth, t_alt_count)
+    pub depth: Option<u32>,
+    pub alt_depth: Option<u32>,
+    /// named columns of MAF files and extra columns of tsv files with header
+    pub fields: Vec<(String, String)>,
+}
+
+impl Variant {
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn open_text(path: &PathBuf) -> Box<dyn BufRead> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(error) => panic!("Problem opening the file {:?}: {:?}", path, error),
+    };
+    // plain gzip (as most MAF files) or bgzf, which is multi member gzip
+    if path.extension().is_some_and(|x| x == "gz") {
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    } else {
+        Box::new(BufReader::new(file))
+    }
+}
+
+fn reference_base(
+    reference: &mut IndexedFastaReader,
+    chrom: &str,
+    pos: usize,
+) -> String {
+    let position = core::Position::try_from(pos).unwrap();
+    let region = core::Region::new(chrom, position..=position);
+    let record = reference.query(&region).unwrap();
+    String::from_utf8_lossy(record.sequence().as_ref()).to_string()
+}
+
+// converts MAF style indels ("-" alleles, insertions placed at the base
+// before, deletions at the first deleted base) to anchored vcf alleles.
+// None for deletions at the first base of the contig, that have no base
+// before to anchor them
+fn anchor_alleles(
+    chrom: &str,
+    start: usize,
+    reference_allele: &str,
+    alternate_allele: &str,
+    reference: Option<&mut IndexedFastaReader>,
+) -> Option<(usize, String, String)> {
+    let reference = match reference {
+        Some(reference) => reference,
+        None => return Some((start, reference_allele.to_string(), alternate_allele.to_string())),
+    };
+    if reference_allele == "-" {
+        let anchor = reference_base(reference, chrom, start);
+        Some((start, anchor.clone(), format!("{}{}", anchor, alternate_allele)))
+    } else if alternate_allele == "-" {
+        let anchor_position = start.checked_sub(1).filter(|x| *x > 0)?;
+        let anchor = reference_base(reference, chrom, anchor_position);
+        Some((anchor_position, format!("{}{}", anchor, reference_allele), anchor))
+    } else {
+        Some((start, reference_allele.to_string(), alternate_allele.to_string()))
+    }
+}
+
+fn parse_position(value: &str, path: &PathBuf) -> usize {
+    match value.parse::<usize>() {
+        Ok(position) if position > 0 => position,
+        _ => panic!("Problem parsing the position {} in {:?}", value, path),
+    }
+}
+
+pub fn read_maf(
+    path: PathBuf,
+    mut reference: Option<&mut IndexedFastaReader>,
+) -> Vec<Variant> {
+    let mut lines = open_text(&path).lines().map(|x| x.unwrap()).filter(|x| !x.starts_with('#'));
+
+    let header: Vec<String> = match lines.next() {
+        Some(line) => line.split('\t').map(String::from).collect(),
+        None => return Vec::new(),
+    };
+    let column = |name: &str| header.iter().position(|x| x == name);
+    let required = |name: &str| match column(name) {
+        Some(idx) => idx,
+        None => panic!("Column {} not found in {:?}", name, path),
+    };
+
+    let chrom_idx = required("Chromosome");
+    let start_idx = required("Start_Position");
+    let ref_idx = required("Reference_Allele");
+    let allele1_idx = required("Tumor_Seq_Allele1");
+    let allele2_idx = required("Tumor_Seq_Allele2");
+    let sample_idx = column("Tumor_Sample_Barcode");
+    let depth_idx = column("t_depth");
+    let alt_depth_idx = column("t_alt_count");
+
+    let mut variants = Vec::new();
+    for line in lines {
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let get = |idx: usize| fields.get(idx).copied().unwrap_or("");
+
+        let reference_allele = get(ref_idx);
+        // the alternative allele is the tumour allele that differs from the reference
+        let alternate_allele = if get(allele2_idx) != reference_allele {
+            get(allele2_idx)
+        } else {
+            get(allele1_idx)
+        };
+
+        let start = parse_position(get(start_idx), &path);
+        let (position, reference_allele, alternate_allele) = match anchor_alleles(
+            get(chrom_idx),
+            start,
+            reference_allele,
+            alternate_allele,
+            reference.as_deref_mut(),
+        ) {
+            Some(alleles) => alleles,
+            None => {
+                eprintln!("Skipping the deletion at {}:{}, there is no base before it to anchor it", get(chrom_idx), start);
+                continue;
+            },
+        };
+
+        variants.push(Variant {
+            chrom: get(chrom_idx).to_string(),
+            position,
+            reference: reference_allele,
+            alternate: alternate_allele,
+            sample: sample_idx.map(|idx| get(idx).to_string()),
+            depth: depth_idx.and_then(|idx| get(idx).parse().ok()),
+            alt_depth: alt_depth_idx.and_then(|idx| get(idx).parse().ok()),
+            fields: header
+                .iter()
+                .zip(fields.iter())
+                .map(|(name, value)| (name.clone(), value.to_string()))
+                .collect(),
+        });
+    }
+    variants
+}
+
+/// Reads a tab separated file with chrom, pos, ref, alt and an optional
+/// sample column. If the first line is a header (the position is not a
+/// number) the columns after the sample are kept as named fields.
+pub fn read_tsv(
+    path: PathBuf,
+    mut reference: Option<&mut IndexedFastaReader>,
+) -> Vec<Variant> {
+    let mut header: Option<Vec<String>> = None;
+    let mut variants = Vec::new();
+
+    for (i, line) in open_text(&path).lines().enumerate() {
+        let line = line.unwrap();
+        if line.is_empty() || (line.starts_with('#') && i > 0) {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 4 {
+            panic!("Problem reading the line {} in {:?}, at least 4 columns are needed", i + 1, path);
+        }
+        if i == 0 && fields[1].parse::<usize>().is_err() {
+            header = Some(fields.iter().map(|x| x.to_string()).collect());
+            continue;
+        }
+
+        let start = parse_position(fields[1], &path);
+        let (position, reference_allele, alternate_allele) = match anchor_alleles(
+            fields[0],
+            start,
+            fields[2],
+            fields[3],
+            reference.as_deref_mut(),
+        ) {
+            Some(alleles) => alleles,
+            None => {
+                eprintln!("Skipping the deletion at {}:{}, there is no base before it to anchor it", fields[0], start);
+                continue;
+            },
+        };
+
+        let extra_fields = match &header {
+            Some(names) => names
+                .iter()
+                .zip(fields.iter())
+                .skip(5)
+                .map(|(name, value)| (name.clone(), value.to_string()))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        variants.push(Variant {
+            chrom: fields[0].to_string(),
+            position,
+            reference: reference_allele,
+            alternate: alternate_allele,
+            sample: fields.get(4).map(|x| x.to_string()),
+            depth: None,
+            alt_depth: None,
+            fields: extra_fields,
+        });
+    }
+    variants
+}
+
+pub fn read_variants(
+    path: PathBuf,
+    format: InputFormat,
+    reference: Option<&mut IndexedFastaReader>,
+) -> Vec<Variant> {
+    match format {
+        InputFormat::Maf => read_maf(path, reference),
+        InputFormat::Tsv => read_tsv(path, reference),
+        InputFormat::Vcf => panic!("vcf files are read with noodles"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use noodles::fasta;
+
+    // chr1 ACGTACGTAC in memory
+    fn reference() -> IndexedFastaReader {
+        let data = b">chr1\nACGTACGTAC\n".to_vec();
+        let index = vec![fasta::fai::Record::new("chr1", 10, 6, 10, 11)];
+        fasta::IndexedReader::new(Box::new(std::io::Cursor::new(data)), index)
+    }
+
+    #[test]
+    fn anchor_alleles_converts_maf_indels() {
+        // start, ref, alt and the expected vcf position and alleles
+        let cases = [
+            (5, "A", "C", Some((5, "A", "C"))),
+            // insertion after the first base
+            (1, "-", "TT", Some((1, "A", "ATT"))),
+            // deletion of G, anchored to the C before
+            (3, "G", "-", Some((2, "CG", "C"))),
+            (4, "TA", "-", Some((3, "GTA", "G"))),
+            // nothing before the first base
+            (1, "A", "-", None),
+        ];
+        let mut reference = reference();
+        for (start, reference_allele, alternate_allele, expected) in cases {
+            let alleles = anchor_alleles("chr1", start, reference_allele, alternate_allele, Some(&mut reference));
+            let expected = expected.map(|(position, reference, alternate)| (position, reference.to_string(), alternate.to_string()));
+            assert_eq!(alleles, expected, "{} {} {}", start, reference_allele, alternate_allele);
+        }
+    }
+
+    #[test]
+    fn anchor_alleles_without_reference_keeps_the_alleles() {
+        let alleles = anchor_alleles("chr1", 1, "A", "-", None);
+        assert_eq!(alleles, Some((1, String::from("A"), String::from("-"))));
+    }
+
+    #[test]
+    fn read_maf_anchors_indels_and_keeps_the_columns() {
+        let path = std::env::temp_dir().join(format!("hvariant_test_{}.maf", std::process::id()));
+        std::fs::write(&path, "#version 2.4\n\
+            Hugo_Symbol\tChromosome\tStart_Position\tReference_Allele\tTumor_Seq_Allele1\tTumor_Seq_Allele2\tTumor_Sample_Barcode\tt_depth\tt_alt_count\n\
+            GENE1\tchr1\t5\tA\tA\tC\tT1\t20\t5\n\
+            GENE2\tchr1\t1\t-\t-\tTT\tT1\t.\t.\n\
+            GENE3\tchr1\t1\tA\tA\t-\tT2\t10\t2\n\
+            GENE4\tchr1\t4\tTA\t-\t-\tT2\t10\t2\n").unwrap();
+        let mut reference = reference();
+        let variants = read_maf(path.clone(), Some(&mut reference));
+        std::fs::remove_file(&path).unwrap();
+
+        let found: Vec<_> = variants
+            .iter()
+            .map(|x| (x.position, x.reference.as_str(), x.alternate.as_str(), x.sample.as_deref(), x.depth, x.field("Hugo_Symbol")))
+            .collect();
+        assert_eq!(found, vec![
+            (5, "A", "C", Some("T1"), Some(20), Some("GENE1")),
+            (1, "A", "ATT", Some("T1"), None, Some("GENE2")),
+            // GENE3 can't be anchored, both tumour alleles are "-" in GENE4
+            (3, "GTA", "G", Some("T2"), Some(10), Some("GENE4")),
+        ]);
+    }
+}
@@ -3,11 +3,26 @@ mod core;
 mod ms;
 mod readinfo;
 mod getrf;
+mod replication;
+mod catalogue;
+mod samples;
+mod input;
+mod alignment;
+mod readfilter;
+mod stats;
+mod artefacts;
+mod reads;
+mod parallel;
+mod umi;
+#[cfg(test)]
+mod testutil;
 
 use std::path::PathBuf;
 
 use clap::{Args, Parser, Subcommand};
 
+use varianth_core::codon::GeneticCode;
+
 #[derive(Parser)]
 #[command(author="DMP", version, about="A compilation of utilities for variant data", long_about = None)]
 #[command(propagate_version = true)]
@@ -22,11 +37,68 @@ enum Commands {
     Addms(AddmsArgs),
     Readinfo(ReadinfoArgs),
     Readfreq(ReadfreqArgs),
+    /// Counts single base substitutions by mutation subtype from an addms vcf
+    Catalogue(CatalogueArgs),
 }
 
 // to add a non-positional argument, we need to put this above the arg.
 //#[clap(short, long)]
 
+#[derive(Args)]
+struct SampleArgs {
+    /// Samples to use (comma separated), all the samples in the vcf by default.
+    #[clap(long, value_delimiter = ',')]
+    samples: Option<Vec<String>>,
+    /// Minimum depth (DP or the sum of AD) for a sample to carry a variant.
+    #[clap(long, default_value = "0")]
+    min_depth: u32,
+    /// Minimum variant allele frequency (from AD) for a sample to carry a variant.
+    #[clap(long, default_value = "0")]
+    min_vaf: f64,
+}
+
+impl SampleArgs {
+    fn to_filter(&self) -> samples::SampleFilter {
+        samples::SampleFilter {
+            samples: self.samples.clone(),
+            min_depth: self.min_depth,
+            min_vaf: self.min_vaf,
+        }
+    }
+}
+
+#[derive(Args)]
+struct ReadFilterArgs {
+    /// Minimum mapping quality of the reads.
+    #[clap(long, default_value = "0")]
+    min_mapq: u8,
+    /// Minimum base quality at the queried bases.
+    #[clap(long, default_value = "0")]
+    min_baseq: u8,
+    /// Reads with any of these flags are removed (number, hex or names as in samtools, i.e. UNMAP,DUP). By default UNMAP,SECONDARY,QCFAIL,DUP and, in readinfo only, SUPPLEMENTARY.
+    #[clap(long, value_parser = readfilter::parse_flags)]
+    exclude_flags: Option<u16>,
+    /// Only reads with all of these flags are used.
+    #[clap(long, default_value = "0", value_parser = readfilter::parse_flags)]
+    include_flags: u16,
+    /// Only reads in a proper pair are used.
+    #[clap(long, action)]
+    proper_pair: bool,
+}
+
+impl ReadFilterArgs {
+    // each command has its own default flags
+    fn to_filter(&self, default_exclude_flags: u16) -> readfilter::ReadFilter {
+        readfilter::ReadFilter {
+            min_mapq: self.min_mapq,
+            min_baseq: self.min_baseq,
+            exclude_flags: self.exclude_flags.unwrap_or(default_exclude_flags),
+            include_flags: self.include_flags,
+            proper_pair: self.proper_pair,
+        }
+    }
+}
+
 #[derive(Args)]
 struct AddmsArgs {
     /// Integer value to define the number of subtype adjacent bases, use 1 for trinucleotide.
@@ -38,6 +110,9 @@ struct AddmsArgs {
     /// VCF file with to modify. (needs to be indexed)
     #[clap(short, long, conflicts_with = "use_stdin")]
     variants: Option<PathBuf>,
+    /// Format of the variants file, guessed from the extension by default. MAF and tsv files are written as tsv.
+    #[clap(long, value_enum)]
+    input_format: Option<input::InputFormat>,
     /// A flag to indicate variants come from stdin instead of a file.
     #[clap(long, action)]
     use_stdin: bool,
@@ -53,32 +128,148 @@ struct AddmsArgs {
     /// Description for the information field to add into the vcf.
     #[clap(short = 'I', long, default_value = "mutation subtype")]
     infodescription: Option<String>,
+    /// bedGraph with a replication timing profile, adds the RT (timing bin) field.
+    #[clap(long)]
+    rt_bedgraph: Option<PathBuf>,
+    /// Number of quantile bins used for the replication timing.
+    #[clap(long, default_value = "4")]
+    rt_bins: usize,
+    /// BED6 with fork directions (+ rightward, - leftward), adds the RS (replication strand) field.
+    #[clap(long, conflicts_with = "origins")]
+    forks: Option<PathBuf>,
+    /// BED with replication origins, adds the RS (replication strand) field.
+    #[clap(long)]
+    origins: Option<PathBuf>,
+    /// With any of these options only the selected samples are written and
+    /// records that none of them carries are dropped.
+    #[command(flatten)]
+    sample_args: SampleArgs,
+    /// Number of threads, more than one needs a bgzipped and indexed vcf. The output is the same as with one thread.
+    #[clap(short, long, default_value = "1")]
+    threads: usize,
+    /// Size in bases of the regions processed by each thread, 0 to split by contig only.
+    #[clap(long, default_value = "10000000")]
+    chunk_size: usize,
 }
 
 #[derive(Args)]
-struct ReadinfoArgs {
-    /// BAM file with read information. (needs to be indexed)
+struct CatalogueArgs {
+    /// VCF file annotated with addms, or any variants file with --genome.
+    #[clap(short, long)]
+    variants: Option<PathBuf>,
+    /// Format of the variants file, guessed from the extension by default.
+    #[clap(long, value_enum)]
+    input_format: Option<input::InputFormat>,
+    /// Fasta file with the reference genome (needs to be indexed), used for the context instead of the MS field.
     #[clap(short, long)]
-    reads: Option<PathBuf>,
+    genome: Option<PathBuf>,
+    /// Number of adjacent bases used with --genome, use 1 for trinucleotide.
+    #[clap(short, long, default_value = "1")]
+    kval: u8,
+    /// Output tsv file
+    #[clap(short = 'o', long, default_value = "catalogue.tsv")]
+    outfile: Option<PathBuf>,
+    /// Name of the information field with the mutation subtype.
+    #[clap(short, long, default_value = "MS")]
+    infoname: Option<String>,
+    /// Information fields used to stratify the counts (i.e. RS,RT).
+    #[clap(short, long, value_delimiter = ',')]
+    stratify: Vec<String>,
+    /// Sample name used in the header for vcf files without samples, defaults to the vcf file name.
+    #[clap(short, long)]
+    name: Option<String>,
+    #[command(flatten)]
+    sample_args: SampleArgs,
+}
+
+#[derive(Args)]
+struct ReadinfoArgs {
+    /// BAM, CRAM or SAM files with read information, one for each sample. (BAM and CRAM need to be indexed)
+    #[clap(short, long, num_args = 1..)]
+    reads: Vec<PathBuf>,
+    /// Sample labels of the reads files (comma separated, same order), by default the SM of the read groups.
+    #[clap(short, long, value_delimiter = ',')]
+    labels: Vec<String>,
+    /// Fasta file with the reference genome to decode CRAM files and count the mismatches of reads without NM tag. (needs to be indexed)
+    #[clap(long)]
+    reference: Option<PathBuf>,
     /// VCF file with to modify. (needs to be indexed)
     #[clap(short, long)]
     variants: Option<PathBuf>,
     /// Output json file
     #[clap(short = 'o', long, default_value = "out.json")]
     outfile: Option<PathBuf>,
+    /// Output tsv file with the ref, alt and other read counts of each variant.
+    #[clap(short, long)]
+    table: Option<PathBuf>,
+    /// Output vcf with the read evidence of each variant as format fields (RI_*), one sample column for each reads file.
+    #[clap(long)]
+    vcf_out: Option<PathBuf>,
+    /// Output tsv with the strand and pair orientation of the alternative reads by mutation type.
+    #[clap(long)]
+    artefacts: Option<PathBuf>,
+    /// Name of the information field with the mutation subtype, used for the artefacts summary.
+    #[clap(short, long, default_value = "MS")]
+    infoname: String,
+    /// Report the distance to the closest end of the read instead of the sequencing cycle.
+    #[clap(long, action)]
+    nearest_end: bool,
+    /// Report the position as a percentage of the read length.
+    #[clap(long, action)]
+    normalise: bool,
+    /// Walk the sorted vcf and the reads together instead of querying the reads of each variant.
+    #[clap(long, action)]
+    streaming: bool,
+    /// Count fragments instead of reads: overlapping mates of a pair are one observation, the mate with the higher base quality is kept.
+    #[clap(long, action)]
+    fragments: bool,
+    /// Number of threads, each one reads its own chunks of variants. The output is the same as with one thread. With --streaming the reads across two chunks are decoded by both, chunks end at gaps of 1kb between variants when possible.
+    #[clap(long, default_value = "1")]
+    threads: usize,
+    /// Only the variants carried by the selected samples are used.
+    #[command(flatten)]
+    sample_args: SampleArgs,
+    #[command(flatten)]
+    read_filter_args: ReadFilterArgs,
 }
 
 #[derive(Args)]
 struct ReadfreqArgs {
-    /// BAM file with read information. (needs to be indexed)
-    #[clap(short, long)]
-    reads: Option<PathBuf>,
+    /// BAM, CRAM or SAM files with read information, one for each sample. (BAM and CRAM need to be indexed)
+    #[clap(short, long, num_args = 1..)]
+    reads: Vec<PathBuf>,
+    /// Sample labels of the reads files (comma separated, same order), by default the SM of the read groups.
+    #[clap(short, long, value_delimiter = ',')]
+    labels: Vec<String>,
+    /// Fasta file with the reference genome, to decode CRAM files and compare the sequences with the reference. (needs to be indexed)
+    #[clap(long)]
+    reference: Option<PathBuf>,
     /// BED file with codons.
     #[clap(short, long)]
     variants: Option<PathBuf>,
-    /// Output json file
+    /// Output tsv file, sorted by contig (as in the reads header), start and end of the intervals (replaced at the end of the run)
     #[clap(short = 'o', long, default_value = "out.tsv")]
     outfile: Option<PathBuf>,
+    /// Number of threads, each one reads its own chunks of the bed file.
+    #[clap(short, long, default_value = "1")]
+    threads: usize,
+    /// The intervals are codons (BED6 with strand), translate the sequences and report the reference codon (needs --reference)
+    #[clap(long)]
+    codons: bool,
+    /// NCBI genetic code used to translate the codons
+    #[clap(long, default_value = "1")]
+    genetic_code: u8,
+    /// Count fragments instead of reads: overlapping mates of a pair are merged, taking the base with the higher quality, and a discordant column counts the fragments whose mates differed.
+    #[clap(long, action)]
+    fragments: bool,
+    /// Tag with the UMI of the reads (i.e. RX or MI), the reads of each UMI family are collapsed to a consensus and the families are counted next to the reads
+    #[clap(long)]
+    umi_tag: Option<String>,
+    /// Minimum reads of a UMI family to count it
+    #[clap(long, default_value = "1")]
+    min_family_size: usize,
+    #[command(flatten)]
+    read_filter_args: ReadFilterArgs,
 }
 
 fn main() {
@@ -89,29 +280,61 @@ fn main() {
     match &cli.command {
         Commands::Addms(addmsargs) => {
             // println!("'myapp add' was used, name is: {:?}", addmsargs.infoname);
-            let kval_in: usize = TryInto::try_into(addmsargs.kval).unwrap();
+            let kval_in: usize = From::from(addmsargs.kval);
             let info_name = addmsargs.infoname.clone().unwrap();
             let info_description = addmsargs.infodescription.clone().unwrap();
             let use_stdin = addmsargs.use_stdin;
             let use_stdout = addmsargs.use_stdout;
-            ms::addms(
-                addmsargs.genome.clone().unwrap(),
-                addmsargs.variants.clone().unwrap(),
-                addmsargs.outfile.clone().unwrap(),
-                kval_in,
-                info_name,
-                info_description,
-                use_stdin,
-                use_stdout,
-            );
+
+            // without any track the replication fields are not added
+            let replication = replication::ReplicationAnnotator {
+                timing: addmsargs.rt_bedgraph.clone().map(|path| {
+                    replication::ReplicationTiming::from_bedgraph(path, addmsargs.rt_bins)
+                }),
+                forks: addmsargs.forks.clone().map(replication::ForkTrack::from_bed),
+                origins: addmsargs.origins.clone().map(replication::OriginTrack::from_bed),
+            };
+
+            let variants_file = addmsargs.variants.clone().unwrap();
+            let input_format = match addmsargs.input_format {
+                Some(input_format) => input_format,
+                None => input::InputFormat::from_path(&variants_file),
+            };
+
+            if input_format == input::InputFormat::Vcf {
+                ms::addms(
+                    addmsargs.genome.clone().unwrap(),
+                    variants_file,
+                    addmsargs.outfile.clone().unwrap(),
+                    kval_in,
+                    info_name,
+                    info_description,
+                    use_stdin,
+                    use_stdout,
+                    replication,
+                    addmsargs.sample_args.to_filter(),
+                    addmsargs.threads,
+                    addmsargs.chunk_size,
+                );
+            } else {
+                ms::addms_table(
+                    addmsargs.genome.clone().unwrap(),
+                    variants_file,
+                    addmsargs.outfile.clone().unwrap(),
+                    input_format,
+                    kval_in,
+                    info_name,
+                    replication,
+                    addmsargs.sample_args.to_filter(),
+                );
+            }
         },
         Commands::Readinfo(readinfoargs) => {
 
-            let reads_file_result = readinfoargs.reads.clone();
-            let reads_file = match reads_file_result {
-                Some(reads_file) => reads_file,
-                None => panic!("No reads file provided"),
-            };
+            let reads_files = readinfoargs.reads.clone();
+            if reads_files.is_empty() {
+                panic!("No reads file provided");
+            }
 
             let variants_file_result = readinfoargs.variants.clone();
             let variants_file = match variants_file_result {
@@ -126,19 +349,37 @@ fn main() {
             };
 
             readinfo::readinfo(
-                reads_file,
+                reads::ReadsInput {
+                    paths: reads_files,
+                    labels: readinfoargs.labels.clone(),
+                    reference: readinfoargs.reference.clone(),
+                },
                 variants_file,
-                outfile,
+                readinfo::ReadinfoOutput {
+                    json: outfile,
+                    table: readinfoargs.table.clone(),
+                    vcf: readinfoargs.vcf_out.clone(),
+                    artefacts: readinfoargs.artefacts.clone(),
+                },
+                readinfoargs.infoname.clone(),
+                readinfo::ReadPosition {
+                    from_nearest_end: readinfoargs.nearest_end,
+                    normalise: readinfoargs.normalise,
+                },
+                readinfoargs.read_filter_args.to_filter(readfilter::READINFO_EXCLUDE_FLAGS),
+                readinfoargs.sample_args.to_filter(),
+                readinfoargs.streaming,
+                readinfoargs.fragments,
+                readinfoargs.threads,
             );
         },
 
         Commands::Readfreq(readfreqargs) => {
 
-            let reads_file_result = readfreqargs.reads.clone();
-            let reads_file = match reads_file_result {
-                Some(reads_file) => reads_file,
-                None => panic!("No reads file provided"),
-            };
+            let reads_files = readfreqargs.reads.clone();
+            if reads_files.is_empty() {
+                panic!("No reads file provided");
+            }
 
             let variants_file_result = readfreqargs.variants.clone();
             let variants_file = match variants_file_result {
@@ -152,10 +393,74 @@ fn main() {
                 None => panic!("No outfile provided"),
             };
 
+            let genetic_code = if readfreqargs.codons {
+                match GeneticCode::ncbi(readfreqargs.genetic_code) {
+                    Some(genetic_code) => Some(genetic_code),
+                    None => panic!("Problem with the genetic code {}, available: {:?}", readfreqargs.genetic_code, GeneticCode::ids()),
+                }
+            } else {
+                None
+            };
+
+            let umi_families = readfreqargs.umi_tag.as_ref().map(|tag| umi::UmiFamilies {
+                tag: tag
+                    .parse()
+                    .unwrap_or_else(|e| panic!("Problem with the UMI tag {}: {:?}", tag, e)),
+                min_family_size: readfreqargs.min_family_size,
+            });
+
             getrf::readfreq(
-                reads_file,
+                reads::ReadsInput {
+                    paths: reads_files,
+                    labels: readfreqargs.labels.clone(),
+                    reference: readfreqargs.reference.clone(),
+                },
+                variants_file,
+                outfile,
+                readfreqargs.read_filter_args.to_filter(readfilter::READFREQ_EXCLUDE_FLAGS),
+                readfreqargs.threads,
+                genetic_code,
+                getrf::ReadCounting {
+                    fragments: readfreqargs.fragments,
+                    umi: umi_families,
+                },
+            );
+        },
+
+        Commands::Catalogue(catalogueargs) => {
+
+            let variants_file_result = catalogueargs.variants.clone();
+            let variants_file = match variants_file_result {
+                Some(variants_file) => variants_file,
+                None => panic!("No variants file provided"),
+            };
+
+            let outfile_result = catalogueargs.outfile.clone();
+            let outfile = match outfile_result {
+                Some(outfile) => outfile,
+                None => panic!("No outfile provided"),
+            };
+
+            let sample_name = match catalogueargs.name.clone() {
+                Some(name) => name,
+                None => catalogue::default_sample_name(&variants_file),
+            };
+
+            let input_format = match catalogueargs.input_format {
+                Some(input_format) => input_format,
+                None => input::InputFormat::from_path(&variants_file),
+            };
+
+            catalogue::catalogue(
                 variants_file,
                 outfile,
+                input_format,
+                catalogueargs.genome.clone(),
+                From::from(catalogueargs.kval),
+                catalogueargs.infoname.clone().unwrap(),
+                catalogueargs.stratify.clone(),
+                sample_name,
+                catalogueargs.sample_args.to_filter(),
             );
         },
     }
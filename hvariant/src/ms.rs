@@ -1,9 +1,11 @@
 
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
 
 use noodles::core;
-use noodles::fasta;
 use noodles::fasta::indexed_reader::Builder;
 
 use noodles::vcf;
@@ -11,21 +13,23 @@ use noodles::vcf::header::record::value::map::Info;
 use noodles::vcf::header::record::value::Map;
 use noodles::vcf::header::Number;
 
+use crate::core::{IndexedFastaReader, ReferenceCache, REFERENCE_WINDOW};
+use crate::input::{read_variants, InputFormat};
+use crate::parallel::process_chunks;
+use crate::replication::ReplicationAnnotator;
+use crate::samples::SampleFilter;
+
 // regions and positions are 1-based (!!!)
 // for how to write custom fields in header and in the record see
 // https://github.com/zaeleus/noodles/issues/160#issuecomment-1509508247
 
-fn get_ntp_from_record(
-    vcf_record: vcf::Record,
-    fasta_index_reader: &mut fasta::IndexedReader<
-        Box<dyn noodles::fasta::io::BufReadSeek>,
-    >,
+pub fn get_ntp(
+    chrom: &str,
+    pos: usize,
+    fasta_index_reader: &mut IndexedFastaReader,
     k: usize,
 ) -> String {
-    let pos1 = core::Position::try_from(usize::from(
-        vcf_record.position(),
-    ))
-    .unwrap();
+    let pos1 = core::Position::try_from(pos).unwrap();
     let end = pos1.checked_add(k).unwrap();
     // for some reason there is no substr method in the noodles?
     let start = core::Position::try_from(
@@ -33,21 +37,147 @@ fn get_ntp_from_record(
     )
     .unwrap();
 
-    let chrom = vcf_record.chromosome().to_string();
     let tntp_region = core::Region::new(chrom, start..=end);
 
     let tntp =
         fasta_index_reader.query(&tntp_region).unwrap();
 
-    let out_str = String::try_from(
+    String::from(
         std::str::from_utf8(tntp.sequence().as_ref())
             .unwrap(),
     )
-    .unwrap();
-    out_str
 }
 
-pub fn addms(genome: PathBuf, variants_in: PathBuf, variants_out: PathBuf, kval: usize, key_name: String, key_description: String, use_stdin: bool, use_stdout: bool) {
+// same as get_ntp, reading from the cached reference window
+fn get_ntp_cached(
+    chrom: &str,
+    pos: usize,
+    reference: &mut ReferenceCache,
+    k: usize,
+) -> String {
+    let start = pos.checked_sub(k).filter(|x| *x > 0).unwrap();
+    String::from_utf8_lossy(reference.sequence(chrom, start, pos + k)).to_string()
+}
+
+// header lines for the replication annotation
+const RS_KEY: &str = "RS";
+const RS_DESCRIPTION: &str = "replication strand of the pyrimidine reference base (leading/lagging)";
+const RT_KEY: &str = "RT";
+const RT_DESCRIPTION: &str = "replication timing bin (1 is the lowest value of the profile)";
+
+
+// everything needed to annotate a record, shared by the serial run and
+// the threads of the parallel one
+struct MsAnnotation<'a> {
+    kval: usize,
+    ms_key: vcf::record::info::field::Key,
+    rs_key: vcf::record::info::field::Key,
+    rt_key: vcf::record::info::field::Key,
+    replication: &'a ReplicationAnnotator,
+    sample_filter: &'a SampleFilter,
+    sample_indices: &'a [(usize, String)],
+}
+
+impl MsAnnotation<'_> {
+    // returns None for records that none of the selected samples carries
+    fn annotate(&self, record: &vcf::Record, reference: &mut ReferenceCache) -> Option<vcf::Record> {
+        if !self.sample_filter.passes_record(record, self.sample_indices) {
+            return None;
+        }
+        let mut record_out = record.clone();
+        self.sample_filter.subset_record(&mut record_out, self.sample_indices);
+        let chrom = record.chromosome().to_string();
+        let pos = usize::from(record.position());
+        let ref_base = record.reference_bases().to_string().as_bytes()[0];
+        let tntp_results = get_ntp_cached(&chrom, pos, reference, self.kval);
+        record_out.info_mut().insert(
+            self.ms_key.clone(),
+            Some(vcf::record::info::field::Value::String(
+                tntp_results,
+            )),
+        );
+
+        // variants outside the tracks are left without annotation
+        if let Some(strand) = self.replication.strand(&chrom, pos, ref_base) {
+            record_out.info_mut().insert(
+                self.rs_key.clone(),
+                Some(vcf::record::info::field::Value::String(
+                    strand.to_string(),
+                )),
+            );
+        }
+        if let Some(bin) = self.replication.timing_bin(&chrom, pos) {
+            record_out.info_mut().insert(
+                self.rt_key.clone(),
+                Some(vcf::record::info::field::Value::Integer(
+                    bin as i32,
+                )),
+            );
+        }
+        Some(record_out)
+    }
+}
+
+// region of the vcf processed by one thread, records are assigned to the
+// chunk that contains their position so none is written twice
+struct Chunk {
+    chrom: String,
+    start: usize,
+    end: Option<usize>,
+}
+
+impl Chunk {
+    fn region(&self) -> core::Region {
+        let start = core::Position::try_from(self.start).unwrap();
+        match self.end {
+            Some(end) => core::Region::new(self.chrom.as_str(), start..=core::Position::try_from(end).unwrap()),
+            None => core::Region::new(self.chrom.as_str(), start..),
+        }
+    }
+
+    fn contains(&self, pos: usize) -> bool {
+        pos >= self.start && self.end.is_none_or(|end| pos <= end)
+    }
+}
+
+// chunks in the order of the contigs in the vcf index, contigs that are not
+// in the fasta index are kept whole
+fn vcf_chunks(vcf_path: &Path, reference_reader: &IndexedFastaReader, chunk_size: usize) -> Vec<Chunk> {
+    let variants_reader = match vcf::indexed_reader::Builder::default().build_from_path(vcf_path) {
+        Ok(reader) => reader,
+        Err(error) => panic!("Problem opening the indexed vcf {:?}, --threads needs a bgzipped and indexed vcf: {:?}", vcf_path, error),
+    };
+    let contig_names = match variants_reader.index().header() {
+        Some(index_header) => index_header.reference_sequence_names().clone(),
+        None => panic!("The index of {:?} has no contig names", vcf_path),
+    };
+
+    let mut chunks = Vec::new();
+    for chrom in contig_names {
+        let length = reference_reader
+            .index()
+            .iter()
+            .find(|x| x.name() == chrom)
+            .map(|x| x.length() as usize);
+        match length {
+            Some(length) if chunk_size > 0 => {
+                let mut start = 1;
+                while start <= length {
+                    let end = (start + chunk_size - 1).min(length);
+                    // the last chunk is open, in case of records past the end
+                    let end = if end == length { None } else { Some(end) };
+                    chunks.push(Chunk { chrom: chrom.clone(), start, end });
+                    start += chunk_size;
+                }
+            },
+            _ => chunks.push(Chunk { chrom, start: 1, end: None }),
+        }
+    }
+    chunks
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn addms(genome: PathBuf, variants_in: PathBuf, variants_out: PathBuf, kval: usize, key_name: String, key_description: String, _use_stdin: bool, _use_stdout: bool, replication: ReplicationAnnotator, sample_filter: SampleFilter, threads: usize, chunk_size: usize) {
     /*
     let reference_path: PathBuf = Into::into("reference.fa");
     let vcf_path: PathBuf = Into::into("sample.vcf.gz");
@@ -57,14 +187,14 @@ pub fn addms(genome: PathBuf, variants_in: PathBuf, variants_out: PathBuf, kval:
     let vcf_path: PathBuf = variants_in;
     let vcf_path_out: PathBuf = variants_out;
 
-    let mut reference_reader = Builder::default()
-        .build_from_path(reference_path)
+    let reference_reader = Builder::default()
+        .build_from_path(&reference_path)
         .unwrap();
 
     /* here we need to decide if stdin is used, not sure how to do that yet */
 
-    let mut variants_reader = vcf::reader::Builder::default()
-            .build_from_path(vcf_path)
+    let mut variants_reader = vcf::reader::Builder
+            .build_from_path(&vcf_path)
             .unwrap();
 
     let header = variants_reader.read_header().unwrap();
@@ -73,7 +203,12 @@ pub fn addms(genome: PathBuf, variants_in: PathBuf, variants_out: PathBuf, kval:
             .build_from_path(vcf_path_out)
             .unwrap();
 
-    let mut header_out = header.clone();
+    let sample_indices = sample_filter.sample_indices(&header);
+    if sample_filter.is_active() && sample_indices.is_empty() {
+        panic!("The vcf has no samples, sample options can't be used");
+    }
+
+    let mut header_out = sample_filter.subset_header(&header);
     // Parse non-standard keys using `info::Key::from_str`.
     let ms_key: vcf::record::info::field::Key = key_name.parse().unwrap();
     // Create structured header records using `Map<I>`.
@@ -83,26 +218,137 @@ pub fn addms(genome: PathBuf, variants_in: PathBuf, variants_out: PathBuf, kval:
         key_description,
     );
     header_out.infos_mut().insert(ms_key.clone(), ms_value);
+
+    let rs_key: vcf::record::info::field::Key = RS_KEY.parse().unwrap();
+    if replication.has_strand() {
+        let rs_value = Map::<Info>::new(
+            Number::Count(1),
+            noodles::vcf::header::record::value::map::info::Type::String,
+            RS_DESCRIPTION,
+        );
+        header_out.infos_mut().insert(rs_key.clone(), rs_value);
+    }
+
+    let rt_key: vcf::record::info::field::Key = RT_KEY.parse().unwrap();
+    if replication.has_timing() {
+        let rt_value = Map::<Info>::new(
+            Number::Count(1),
+            noodles::vcf::header::record::value::map::info::Type::Integer,
+            RT_DESCRIPTION,
+        );
+        header_out.infos_mut().insert(rt_key.clone(), rt_value);
+    }
+
     writer.write_header(&header_out).unwrap();
 
+    let annotation = MsAnnotation {
+        kval,
+        ms_key,
+        rs_key,
+        rt_key,
+        replication: &replication,
+        sample_filter: &sample_filter,
+        sample_indices: &sample_indices,
+    };
+
+    if threads > 1 {
+        let chunks = vcf_chunks(&vcf_path, &reference_reader, chunk_size);
+        addms_parallel(&reference_path, &vcf_path, &header, &header_out, &annotation, chunks, threads, writer.get_mut());
+        return;
+    }
+
+    let mut reference = ReferenceCache::new(reference_reader, REFERENCE_WINDOW);
     // i think we should map this
     for result in variants_reader.records(&header) {
         let record = result.unwrap();
-        let mut record_out = record.clone();
-        let tntp_results = get_ntp_from_record(
-            record,
-            &mut reference_reader,
-            kval,
-        );
-        record_out.info_mut().insert(
-            ms_key.clone(),
-            Some(vcf::record::info::field::Value::String(
-                tntp_results.clone(),
-            )),
-        );
+        if let Some(record_out) = annotation.annotate(&record, &mut reference) {
+            writer.write_record(&header_out, &record_out).unwrap();
+        }
+    }
+}
+
+// each thread annotates the chunks with its own vcf and fasta readers and
+// returns the formatted records, written in the order of the chunks
+#[allow(clippy::too_many_arguments)]
+fn addms_parallel(reference_path: &Path, vcf_path: &Path, header: &vcf::Header, header_out: &vcf::Header, annotation: &MsAnnotation, chunks: Vec<Chunk>, threads: usize, out: &mut dyn Write) {
+    process_chunks(
+        chunks,
+        threads,
+        || {
+            let variants_reader = vcf::indexed_reader::Builder::default()
+                .build_from_path(vcf_path)
+                .unwrap();
+            let reference_reader = Builder::default()
+                .build_from_path(reference_path)
+                .unwrap();
+            ((variants_reader, ReferenceCache::new(reference_reader, REFERENCE_WINDOW)), ())
+        },
+        |(variants_reader, reference), _, chunk| {
+            let mut chunk_writer = vcf::Writer::new(Vec::new());
+            let query = match variants_reader.query(header, &chunk.region()) {
+                Ok(query) => query,
+                Err(error) => panic!("Problem querying the region {}: {:?}", chunk.region(), error),
+            };
+            for result in query {
+                let record = result.unwrap();
+                if !chunk.contains(usize::from(record.position())) {
+                    continue;
+                }
+                if let Some(record_out) = annotation.annotate(&record, reference) {
+                    chunk_writer.write_record(header_out, &record_out).unwrap();
+                }
+            }
+            chunk_writer.into_inner()
+        },
+        |_, buffer| out.write_all(&buffer).unwrap(),
+    );
+}
+
+/// addms for MAF and tsv files, the output is a tsv with the variants in
+/// vcf coordinates and the annotations as extra columns.
+#[allow(clippy::too_many_arguments)]
+pub fn addms_table(genome: PathBuf, variants_in: PathBuf, table_out: PathBuf, format: InputFormat, kval: usize, key_name: String, replication: ReplicationAnnotator, sample_filter: SampleFilter) {
 
-        writer.write_record(&header, &record_out).unwrap();
+    let mut reference_reader = Builder::default()
+        .build_from_path(genome)
+        .unwrap();
 
+    let variants = read_variants(variants_in, format, Some(&mut reference_reader));
+
+    let mut out_file = BufWriter::new(File::create(table_out).unwrap());
+    write!(out_file, "chrom\tpos\tref\talt\tsample\t{}", key_name).unwrap();
+    if replication.has_strand() {
+        write!(out_file, "\t{}", RS_KEY).unwrap();
     }
-}
+    if replication.has_timing() {
+        write!(out_file, "\t{}", RT_KEY).unwrap();
+    }
+    writeln!(out_file).unwrap();
+
+    for variant in variants {
+        if !sample_filter.passes_variant(&variant) {
+            continue;
+        }
+        let tntp_results = get_ntp(&variant.chrom, variant.position, &mut reference_reader, kval);
+        write!(out_file, "{}\t{}\t{}\t{}\t{}\t{}",
+            variant.chrom,
+            variant.position,
+            variant.reference,
+            variant.alternate,
+            variant.sample.as_deref().unwrap_or("."),
+            tntp_results).unwrap();
 
+        let ref_base = variant.reference.as_bytes()[0];
+        if replication.has_strand() {
+            let strand = replication.strand(&variant.chrom, variant.position, ref_base);
+            write!(out_file, "\t{}", strand.unwrap_or("NA")).unwrap();
+        }
+        if replication.has_timing() {
+            match replication.timing_bin(&variant.chrom, variant.position) {
+                Some(bin) => write!(out_file, "\t{}", bin).unwrap(),
+                None => write!(out_file, "\tNA").unwrap(),
+            }
+        }
+        writeln!(out_file).unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+
+// chunks of work shared by several threads. readers can't be moved between
+// threads, so each worker opens its own, and keeps its own totals that are
+// merged by the caller at the end. the chunks are pulled from the iterator
+// by the calling thread as the workers need them, through a bounded channel,
+// so the whole input is never kept in memory.
+
+/// Processes the chunks with up to `threads` workers and passes the results
+/// to `write` in the order of the chunks. `open` creates the readers and the
+/// totals of each worker, the totals of every worker are returned (at least
+/// one worker runs, also without chunks).
+pub fn process_chunks<C, W, T, R>(
+    chunks: impl IntoIterator<Item = C>,
+    threads: usize,
+    open: impl Fn() -> (W, T) + Sync,
+    process: impl Fn(&mut W, &mut T, &C) -> R + Sync,
+    mut write: impl FnMut(&C, R),
+) -> Vec<T>
+where
+    C: Send,
+    T: Send,
+    R: Send,
+{
+    let threads = threads.max(1);
+    let (chunk_sender, chunk_receiver) = mpsc::sync_channel::<(usize, C)>(threads);
+    let chunk_receiver = Mutex::new(chunk_receiver);
+    let (sender, receiver) = mpsc::channel::<(usize, C, R)>();
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                let sender = sender.clone();
+                let chunk_receiver = &chunk_receiver;
+                let open = &open;
+                let process = &process;
+                scope.spawn(move || {
+                    let (mut readers, mut totals) = open();
+                    loop {
+                        let next = chunk_receiver.lock().unwrap().recv();
+                        let (idx, chunk) = match next {
+                            Ok(next) => next,
+                            Err(_) => break,
+                        };
+                        let result = process(&mut readers, &mut totals, &chunk);
+                        if sender.send((idx, chunk, result)).is_err() {
+                            break;
+                        }
+                    }
+                    totals
+                })
+            })
+            .collect();
+        drop(sender);
+
+        // chunks that finish early wait here until their turn
+        let mut pending: BTreeMap<usize, (C, R)> = BTreeMap::new();
+        let mut next_write = 0;
+        let mut write_ready = |pending: &mut BTreeMap<usize, (C, R)>| {
+            while let Some((chunk, result)) = pending.remove(&next_write) {
+                write(&chunk, result);
+                next_write += 1;
+            }
+        };
+        for (idx, chunk) in chunks.into_iter().enumerate() {
+            // blocks while every worker is busy and the channel is full
+            if chunk_sender.send((idx, chunk)).is_err() {
+                break;
+            }
+            for (idx, chunk, result) in receiver.try_iter() {
+                pending.insert(idx, (chunk, result));
+            }
+            write_ready(&mut pending);
+        }
+        drop(chunk_sender);
+        for (idx, chunk, result) in receiver {
+            pending.insert(idx, (chunk, result));
+            write_ready(&mut pending);
+        }
+
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
@@ -0,0 +1,212 @@
+
+
+use std::collections::HashMap;
+
+use noodles::sam;
+
+// read filters shared by readinfo and readfreq. each removed read is
+// counted in the first filter that it fails, so the counts add up to the
+// number of reads removed.
+
+// flag names as in samtools
+const FLAG_NAMES: [(&str, u16); 12] = [
+    ("PAIRED", 0x1),
+    ("PROPER_PAIR", 0x2),
+    ("UNMAP", 0x4),
+    ("MUNMAP", 0x8),
+    ("REVERSE", 0x10),
+    ("MREVERSE", 0x20),
+    ("READ1", 0x40),
+    ("READ2", 0x80),
+    ("SECONDARY", 0x100),
+    ("QCFAIL", 0x200),
+    ("DUP", 0x400),
+    ("SUPPLEMENTARY", 0x800),
+];
+
+/// Default --exclude-flags of readinfo: unmapped, secondary, QC fail,
+/// duplicate and supplementary reads.
+pub const READINFO_EXCLUDE_FLAGS: u16 = 0xF04;
+/// Default --exclude-flags of readfreq, supplementary alignments are kept
+/// (hard clipped parts of split and long reads).
+pub const READFREQ_EXCLUDE_FLAGS: u16 = 0x704;
+
+/// Parses flags as samtools does: a decimal or hexadecimal (0x) number or
+/// a comma separated list of names (i.e. UNMAP,DUP).
+pub fn parse_flags(value: &str) -> Result<u16, String> {
+    if let Some(hex) = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        return u16::from_str_radix(hex, 16).map_err(|e| e.to_string());
+    }
+    if let Ok(flags) = value.parse::<u16>() {
+        return Ok(flags);
+    }
+    value.split(',').try_fold(0, |flags, name| {
+        match FLAG_NAMES.iter().find(|(x, _)| x.eq_ignore_ascii_case(name.trim())) {
+            Some((_, bit)) => Ok(flags | bit),
+            None => Err(format!("unknown flag {}", name)),
+        }
+    })
+}
+
+pub struct ReadFilter {
+    pub min_mapq: u8,
+    pub min_baseq: u8,
+    /// reads with any of these flags are removed
+    pub exclude_flags: u16,
+    /// reads need all of these flags
+    pub include_flags: u16,
+    /// reads need to be paired and properly aligned
+    pub proper_pair: bool,
+}
+
+/// Number of reads removed by each filter.
+#[derive(Default)]
+pub struct FilterCounts {
+    pub exclude_flags: u64,
+    pub include_flags: u64,
+    pub proper_pair: u64,
+    pub mapq: u64,
+    pub baseq: u64,
+    pub passed: u64,
+}
+
+/// Filter that removed a read, or Passed, in the order of the filters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ReadOutcome {
+    ExcludeFlags,
+    IncludeFlags,
+    ProperPair,
+    Mapq,
+    Baseq,
+    Passed,
+}
+
+/// Where the filters record the outcome of each read.
+pub trait FilterTally {
+    fn add(&mut self, record: &sam::alignment::Record, outcome: ReadOutcome);
+}
+
+impl FilterTally for FilterCounts {
+    fn add(&mut self, _record: &sam::alignment::Record, outcome: ReadOutcome) {
+        self.count(outcome);
+    }
+}
+
+// read name, flags, reference sequence and alignment start, the flags tell
+// apart the mates and the supplementary alignments of a read
+type ReadKey = (Vec<u8>, u16, Option<usize>, Option<usize>);
+
+/// Outcome of the reads of one sample that can be filtered several times,
+/// i.e. reads that overlap several variants. Each read is counted once, as
+/// used if its bases passed for any variant.
+#[derive(Default)]
+pub struct ReadOutcomes {
+    reads: HashMap<ReadKey, ReadOutcome>,
+}
+
+impl FilterTally for ReadOutcomes {
+    fn add(&mut self, record: &sam::alignment::Record, outcome: ReadOutcome) {
+        let name: &[u8] = record.read_name().map(|name| name.as_ref()).unwrap_or_default();
+        let key = (
+            name.to_vec(),
+            u16::from(record.flags()),
+            record.reference_sequence_id(),
+            record.alignment_start().map(usize::from),
+        );
+        let best = self.reads.entry(key).or_insert(outcome);
+        *best = (*best).max(outcome);
+    }
+}
+
+impl ReadOutcomes {
+    pub fn merge(&mut self, other: ReadOutcomes) {
+        for (key, outcome) in other.reads {
+            let best = self.reads.entry(key).or_insert(outcome);
+            *best = (*best).max(outcome);
+        }
+    }
+
+    pub fn counts(&self) -> FilterCounts {
+        let mut counts = FilterCounts::default();
+        for outcome in self.reads.values() {
+            counts.count(*outcome);
+        }
+        counts
+    }
+}
+
+impl FilterCounts {
+    fn count(&mut self, outcome: ReadOutcome) {
+        match outcome {
+            ReadOutcome::ExcludeFlags => self.exclude_flags += 1,
+            ReadOutcome::IncludeFlags => self.include_flags += 1,
+            ReadOutcome::ProperPair => self.proper_pair += 1,
+            ReadOutcome::Mapq => self.mapq += 1,
+            ReadOutcome::Baseq => self.baseq += 1,
+            ReadOutcome::Passed => self.passed += 1,
+        }
+    }
+
+    pub fn merge(&mut self, other: FilterCounts) {
+        self.exclude_flags += other.exclude_flags;
+        self.include_flags += other.include_flags;
+        self.proper_pair += other.proper_pair;
+        self.mapq += other.mapq;
+        self.baseq += other.baseq;
+        self.passed += other.passed;
+    }
+
+    pub fn report(&self) {
+        eprintln!("Reads removed by --exclude-flags: {}", self.exclude_flags);
+        eprintln!("Reads removed by --include-flags: {}", self.include_flags);
+        eprintln!("Reads removed by --proper-pair: {}", self.proper_pair);
+        eprintln!("Reads removed by --min-mapq: {}", self.mapq);
+        eprintln!("Reads removed by --min-baseq: {}", self.baseq);
+        eprintln!("Reads used: {}", self.passed);
+    }
+}
+
+impl ReadFilter {
+    /// Read level filters (flags and mapping quality).
+    pub fn passes_read(&self, record: &sam::alignment::Record, counts: &mut impl FilterTally) -> bool {
+        let flags = u16::from(record.flags());
+        if flags & self.exclude_flags != 0 {
+            counts.add(record, ReadOutcome::ExcludeFlags);
+            return false;
+        }
+        if flags & self.include_flags != self.include_flags {
+            counts.add(record, ReadOutcome::IncludeFlags);
+            return false;
+        }
+        if self.proper_pair && !(record.flags().is_segmented() && record.flags().is_properly_aligned()) {
+            counts.add(record, ReadOutcome::ProperPair);
+            return false;
+        }
+        // a missing mapping quality (255) is not filtered
+        if let Some(mapq) = record.mapping_quality() {
+            if u8::from(mapq) < self.min_mapq {
+                counts.add(record, ReadOutcome::Mapq);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Base quality filter for the queried bases (indices in SEQ), the read
+    /// is only counted as used if it passes.
+    pub fn passes_bases(&self, record: &sam::alignment::Record, read_indices: &[usize], counts: &mut impl FilterTally) -> bool {
+        if self.min_baseq > 0 {
+            let scores = record.quality_scores().as_ref();
+            let low_quality = read_indices.iter().any(|idx| match scores.get(*idx) {
+                Some(score) => u8::from(*score) < self.min_baseq,
+                None => false,
+            });
+            if low_quality {
+                counts.add(record, ReadOutcome::Baseq);
+                return false;
+            }
+        }
+        counts.add(record, ReadOutcome::Passed);
+        true
+    }
+}
@@ -1,71 +1,883 @@
-
-use crate::core::fromvcfrecord2region;
+use crate::alignment::{classify_read, edit_distance, mate_key, merge_mates, pair_orientation, read_cycle, AlleleSupport, PairOrientation, ReadAllele};
+use crate::artefacts::{AltReads, ArtefactSummary};
+use crate::core::{fromvcfrecord2region, ReferenceCache, REFERENCE_WINDOW};
+use crate::parallel::process_chunks;
+use crate::reads::{AlignmentQuery, ReadsInput, SampleReads};
+use crate::readfilter::{FilterCounts, ReadFilter, ReadOutcomes};
+use crate::samples::SampleFilter;
+use crate::stats::{fisher_exact, ks_statistic, mean, median, median_absolute_deviation};
 
 use std::path::PathBuf;
 use std::fs::File;
-use std::io::Write;
-use serde_json;
+use std::io::{BufWriter, Write};
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
 
-use std::collections::HashMap;
+use serde::Serialize;
 
 use noodles::vcf;
+use noodles::vcf::header::record::value::map::format::Type as FormatType;
+use noodles::vcf::header::record::value::map::Format;
+use noodles::vcf::header::record::value::Map;
+use noodles::vcf::header::Number;
+use noodles::vcf::record::genotypes::keys::Key;
+use noodles::vcf::record::genotypes::sample::value::Array;
+use noodles::vcf::record::genotypes::sample::Value;
+use noodles::vcf::record::genotypes::{Genotypes, Keys};
+use noodles::core::{Position, Region};
+use noodles::fasta;
 use noodles::sam;
-use noodles::bam;
+use noodles::sam::record::data::field::tag;
+
+// histogram of a read property, split by the allele that each read supports
+#[derive(Default, Serialize)]
+struct AlleleHistograms {
+    #[serde(rename = "ref")]
+    reference: BTreeMap<usize, u32>,
+    alt: BTreeMap<usize, u32>,
+    other: BTreeMap<usize, u32>,
+}
+
+impl AlleleHistograms {
+    fn add(&mut self, allele: AlleleSupport, value: usize) {
+        let histogram = match allele {
+            AlleleSupport::Ref => &mut self.reference,
+            AlleleSupport::Alt(_) => &mut self.alt,
+            AlleleSupport::Other => &mut self.other,
+        };
+        let stat = histogram.entry(value).or_insert(0);
+        *stat += 1;
+    }
+
+    fn merge(&mut self, other: AlleleHistograms) {
+        for (histogram, other_histogram) in [
+            (&mut self.reference, other.reference),
+            (&mut self.alt, other.alt),
+            (&mut self.other, other.other),
+        ] {
+            for (value, count) in other_histogram {
+                *histogram.entry(value).or_insert(0) += count;
+            }
+        }
+    }
+}
+
+// histograms of all the variants of a sample: the position of the variant
+// in the reads (at the top level, as in older versions), the fragment
+// length (absolute TLEN) of the paired reads and the qualities and
+// mismatches of the reads
+#[derive(Default, Serialize)]
+struct SampleHistograms {
+    #[serde(flatten)]
+    position: AlleleHistograms,
+    fragment_length: AlleleHistograms,
+    /// base quality of the first base of the allele
+    base_quality: AlleleHistograms,
+    mapping_quality: AlleleHistograms,
+    /// edit distance (NM) of the reads
+    mismatches: AlleleHistograms,
+}
+
+impl SampleHistograms {
+    fn merge(&mut self, other: SampleHistograms) {
+        self.position.merge(other.position);
+        self.fragment_length.merge(other.fragment_length);
+        self.base_quality.merge(other.base_quality);
+        self.mapping_quality.merge(other.mapping_quality);
+        self.mismatches.merge(other.mismatches);
+    }
+}
+
+// read that passed the read level filters, with its edit distance (NM tag
+// or computed against the reference) that is only needed once per read
+struct FilteredRead {
+    record: sam::alignment::Record,
+    mismatches: Option<usize>,
+}
+
+impl FilteredRead {
+    fn new(record: sam::alignment::Record, chrom: &str, reference: Option<&mut ReferenceCache>) -> Self {
+        let mismatches = match record.data().get(&tag::EDIT_DISTANCE) {
+            Some(value) => value.as_int().map(|x| x as usize),
+            None => reference.and_then(|reference| {
+                let start = usize::from(record.alignment_start()?);
+                let end = usize::from(record.alignment_end()?);
+                edit_distance(&record, reference.sequence(chrom, start, end), start)
+            }),
+        };
+        FilteredRead { record, mismatches }
+    }
+}
+
+// fragment length of a read, None for reads without mate in the same contig
+fn fragment_length(record: &sam::alignment::Record) -> Option<usize> {
+    match record.template_length() {
+        0 => None,
+        tlen => Some(tlen.unsigned_abs() as usize),
+    }
+}
+
+/// How the position of the variant in the read is reported, by default
+/// the sequencing cycle (0 is the first base at the 5' end of the read).
+pub struct ReadPosition {
+    /// distance to the closest end of the read instead
+    pub from_nearest_end: bool,
+    /// percentage of the read length instead of bases
+    pub normalise: bool,
+}
+
+impl ReadPosition {
+    fn value(&self, cycle: usize, read_length: usize) -> usize {
+        let last_cycle = read_length.saturating_sub(1);
+        let position = if self.from_nearest_end {
+            cycle.min(last_cycle - cycle)
+        } else {
+            cycle
+        };
+        if self.normalise && last_cycle > 0 {
+            (100.0 * position as f64 / last_cycle as f64).round() as usize
+        } else {
+            position
+        }
+    }
+}
+
+// reads supporting one allele of a variant
+#[derive(Default)]
+struct AlleleEvidence {
+    reads: u32,
+    forward_reads: u32,
+    f1r2_reads: u32,
+    f2r1_reads: u32,
+    positions: Vec<f64>,
+    base_qualities: Vec<u8>,
+    mapping_qualities: Vec<u8>,
+    mismatches: Vec<u32>,
+    fragment_lengths: Vec<f64>,
+}
+
+// read evidence of a single variant
+struct VariantEvidence {
+    reference: AlleleEvidence,
+    alternates: Vec<AlleleEvidence>,
+    other_reads: u32,
+    // overlapping mates with different alleles, only counted by fragment
+    discordant_pairs: Option<u32>,
+}
+
+impl VariantEvidence {
+    fn new(n_alleles: usize) -> Self {
+        VariantEvidence {
+            reference: AlleleEvidence::default(),
+            alternates: (0..n_alleles).map(|_| AlleleEvidence::default()).collect(),
+            other_reads: 0,
+            discordant_pairs: None,
+        }
+    }
+
+    fn add(&mut self, read: &FilteredRead, allele: ReadAllele, position: Option<usize>) {
+        let record = &read.record;
+        let allele_evidence = match allele.support {
+            AlleleSupport::Ref => &mut self.reference,
+            AlleleSupport::Alt(idx) => &mut self.alternates[idx],
+            AlleleSupport::Other => {
+                self.other_reads += 1;
+                return;
+            },
+        };
+        allele_evidence.reads += 1;
+        if !record.flags().is_reverse_complemented() {
+            allele_evidence.forward_reads += 1;
+        }
+        match pair_orientation(record) {
+            Some(PairOrientation::F1R2) => allele_evidence.f1r2_reads += 1,
+            Some(PairOrientation::F2R1) => allele_evidence.f2r1_reads += 1,
+            None => {},
+        }
+        if let Some(position) = position {
+            allele_evidence.positions.push(position as f64);
+        }
+        if let Some(length) = fragment_length(record) {
+            allele_evidence.fragment_lengths.push(length as f64);
+        }
+        let score = allele.read_index.and_then(|idx| record.quality_scores().as_ref().get(idx).copied());
+        if let Some(score) = score {
+            allele_evidence.base_qualities.push(u8::from(score));
+        }
+        if let Some(mapq) = record.mapping_quality() {
+            allele_evidence.mapping_qualities.push(u8::from(mapq));
+        }
+        if let Some(mismatches) = read.mismatches {
+            allele_evidence.mismatches.push(mismatches as u32);
+        }
+    }
+
+    fn depth(&self) -> u32 {
+        self.reference.reads + self.alternates.iter().map(|x| x.reads).sum::<u32>() + self.other_reads
+    }
+
+    fn vaf(&self, idx: usize) -> Option<f64> {
+        let depth = self.depth();
+        if depth == 0 {
+            None
+        } else {
+            Some(self.alternates[idx].reads as f64 / depth as f64)
+        }
+    }
+
+    // fisher's exact test of forward and reverse reads, ref against alt
+    fn strand_bias(&self, idx: usize) -> f64 {
+        let alt = &self.alternates[idx];
+        fisher_exact(
+            self.reference.forward_reads as u64,
+            (self.reference.reads - self.reference.forward_reads) as u64,
+            alt.forward_reads as u64,
+            (alt.reads - alt.forward_reads) as u64,
+        )
+    }
+
+    // same test for the pair orientation (F1R2 against F2R1)
+    fn orientation_bias(&self, idx: usize) -> f64 {
+        let alt = &self.alternates[idx];
+        fisher_exact(
+            self.reference.f1r2_reads as u64,
+            self.reference.f2r1_reads as u64,
+            alt.f1r2_reads as u64,
+            alt.f2r1_reads as u64,
+        )
+    }
+
+    // kolmogorov-smirnov statistic of the fragment lengths, ref against alt
+    fn fragment_length_ks(&self, idx: usize) -> Option<f64> {
+        ks_statistic(&self.reference.fragment_lengths, &self.alternates[idx].fragment_lengths)
+    }
+
+    fn alt_reads(&self, idx: usize) -> AltReads {
+        let alt = &self.alternates[idx];
+        AltReads {
+            forward: alt.forward_reads,
+            reverse: alt.reads - alt.forward_reads,
+            f1r2: alt.f1r2_reads,
+            f2r1: alt.f2r1_reads,
+        }
+    }
+}
+
+// variants given to each thread
+const VARIANTS_PER_CHUNK: usize = 1000;
+
+// with --streaming the reads across the end of a chunk are decoded again by
+// the next one, so a full chunk goes on until a gap between variants longer
+// than the reads, with at most STREAMING_CHUNK_LIMIT variants
+const STREAMING_CHUNK_GAP: usize = 1000;
+const STREAMING_CHUNK_LIMIT: usize = 10 * VARIANTS_PER_CHUNK;
+
+const TABLE_HEADER: &str = "chrom\tpos\tref\talt\tsample\tref_reads\talt_reads\tdepth\tstrand_bias\torientation_bias\tref_tlen_median\talt_tlen_median\ttlen_ks\tref_bq_mean\talt_bq_mean\tref_mapq_mean\talt_mapq_mean\tref_nm_mean\talt_nm_mean\tdiscordant_pairs";
+
+// missing values are written as NA
+fn format_optional<T: std::fmt::Display>(value: Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => String::from("NA"),
+    }
+}
+
+fn format_mean<T: Copy + Into<f64>>(values: &[T]) -> String {
+    format_optional(mean(values).map(|x| format!("{:.2}", x)))
+}
+
+// one line per alternative allele, reads with any other alternative allele
+// are counted as other
+fn write_table_rows(out: &mut impl Write, variant: &vcf::Record, sample: &str, evidence: &VariantEvidence) {
+    let depth = evidence.depth();
+    for (idx, allele) in variant.alternate_bases().iter().enumerate() {
+        let alt = &evidence.alternates[idx];
+        writeln!(out, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            variant.chromosome(),
+            variant.position(),
+            variant.reference_bases(),
+            allele,
+            sample,
+            evidence.reference.reads,
+            alt.reads,
+            depth,
+            evidence.strand_bias(idx),
+            evidence.orientation_bias(idx),
+            format_optional(median(&evidence.reference.fragment_lengths)),
+            format_optional(median(&alt.fragment_lengths)),
+            format_optional(evidence.fragment_length_ks(idx).map(|x| format!("{:.4}", x))),
+            format_mean(&evidence.reference.base_qualities),
+            format_mean(&alt.base_qualities),
+            format_mean(&evidence.reference.mapping_qualities),
+            format_mean(&alt.mapping_qualities),
+            format_mean(&evidence.reference.mismatches),
+            format_mean(&alt.mismatches),
+            format_optional(evidence.discordant_pairs)).unwrap();
+    }
+}
+
+// format fields of the vcf output, with the evidence of each reads file in
+// its sample column, one value per alternative allele except for the
+// reference values
+const FORMAT_FIELDS: [(&str, Number, FormatType, &str); 7] = [
+    ("RI_DP", Number::Count(1), FormatType::Integer, "Reads at the variant position"),
+    ("RI_ALT", Number::A, FormatType::Integer, "Reads supporting each alternative allele"),
+    ("RI_VAF", Number::A, FormatType::Float, "Fraction of the reads supporting each alternative allele"),
+    ("RI_POS_MEDIAN", Number::A, FormatType::Float, "Median position of the variant in the alternative reads"),
+    ("RI_POS_MAD", Number::A, FormatType::Float, "Median absolute deviation of the position of the variant in the alternative reads"),
+    ("RI_ALT_BQ", Number::A, FormatType::Float, "Mean base quality of the variant base in the alternative reads"),
+    ("RI_SB", Number::A, FormatType::Float, "Fisher's exact test p-value of the strand of the reference and alternative reads"),
+];
+
+// adds the format fields and a sample column for each reads file whose
+// label is not a sample of the vcf, returns the column of each reads file
+fn add_format_headers(header: &mut vcf::Header, labels: &[String]) -> Vec<usize> {
+    for (key, number, ty, description) in FORMAT_FIELDS {
+        header.formats_mut().insert(key.parse().unwrap(), Map::<Format>::new(number, ty, description));
+    }
+    labels
+        .iter()
+        .map(|label| header.sample_names_mut().insert_full(label.clone()).0)
+        .collect()
+}
+
+fn float_array(values: Vec<Option<f64>>) -> Option<Value> {
+    Some(Value::Array(Array::Float(values.into_iter().map(|x| x.map(|x| x as f32)).collect())))
+}
+
+// values of the format fields for the evidence of one reads file
+fn format_values(evidence: &VariantEvidence) -> Vec<Option<Value>> {
+    let alternates = &evidence.alternates;
+    vec![
+        Some(Value::Integer(evidence.depth() as i32)),
+        Some(Value::Array(Array::Integer(alternates.iter().map(|x| Some(x.reads as i32)).collect()))),
+        float_array((0..alternates.len()).map(|idx| evidence.vaf(idx)).collect()),
+        float_array(alternates.iter().map(|x| median(&x.positions)).collect()),
+        float_array(alternates.iter().map(|x| median_absolute_deviation(&x.positions)).collect()),
+        float_array(alternates.iter().map(|x| mean(&x.base_qualities)).collect()),
+        float_array((0..alternates.len()).map(|idx| Some(evidence.strand_bias(idx))).collect()),
+    ]
+}
+
+// adds the format fields to the genotypes of the (already subset) record,
+// samples without reads file get missing values. format fields of a
+// previous run are replaced
+fn annotate_record(record: &mut vcf::Record, evidences: &[(String, VariantEvidence)], columns: &[usize], n_columns: usize) {
+    let ri_keys: Vec<Key> = FORMAT_FIELDS.iter().map(|(key, _, _, _)| key.parse().unwrap()).collect();
+    let genotypes = record.genotypes();
+    let kept: Vec<usize> = (0..genotypes.keys().len())
+        .filter(|idx| !ri_keys.contains(&genotypes.keys()[*idx]))
+        .collect();
+    let mut keys: Vec<Key> = kept.iter().map(|idx| genotypes.keys()[*idx].clone()).collect();
+    keys.extend(ri_keys);
+    let mut values: Vec<Vec<Option<Value>>> = (0..n_columns)
+        .map(|column| {
+            let sample_values = genotypes.get_index(column).map(|sample| sample.values().to_vec()).unwrap_or_default();
+            kept.iter().map(|idx| sample_values.get(*idx).cloned().flatten()).collect()
+        })
+        .collect();
+    let mut ri_values: Vec<Option<Vec<Option<Value>>>> = vec![None; n_columns];
+    for ((_, evidence), column) in evidences.iter().zip(columns) {
+        ri_values[*column] = Some(format_values(evidence));
+    }
+    for (sample_values, sample_ri_values) in values.iter_mut().zip(ri_values) {
+        sample_values.extend(sample_ri_values.unwrap_or_else(|| vec![None; FORMAT_FIELDS.len()]));
+    }
+    *record.genotypes_mut() = Genotypes::new(Keys::try_from(keys).unwrap(), values);
+}
+
+/// Output files of readinfo, only the json is always written.
+pub struct ReadinfoOutput {
+    pub json: PathBuf,
+    pub table: Option<PathBuf>,
+    pub vcf: Option<PathBuf>,
+    pub artefacts: Option<PathBuf>,
+}
+
+// reference context of the variant for the artefact summary, the MS field
+// written by addms or the reference base
+fn variant_context(variant: &vcf::Record, ms_key: &vcf::record::info::field::Key) -> Vec<u8> {
+    match variant.info().get(ms_key) {
+        Some(Some(vcf::record::info::field::Value::String(context))) => context.clone().into_bytes(),
+        _ => variant.reference_bases().to_string().into_bytes(),
+    }
+}
+
+// vcf writer with the header and the sample column of each reads file
+struct VcfOutput {
+    writer: vcf::Writer<Box<dyn Write>>,
+    header: vcf::Header,
+    columns: Vec<usize>,
+}
+
+// writes the evidence of each variant and sample to the tsv and vcf outputs,
+// and collects the artefacts summary
+struct EvidenceWriters<'a> {
+    table: Option<BufWriter<File>>,
+    vcf: Option<VcfOutput>,
+    artefacts: Option<ArtefactSummary>,
+    ms_key: vcf::record::info::field::Key,
+    sample_filter: &'a SampleFilter,
+    sample_indices: &'a [(usize, String)],
+}
 
-pub fn readinfo(reads: PathBuf, variants_in: PathBuf, json_out: PathBuf) {
+impl EvidenceWriters<'_> {
+    // evidences of one variant, in the order of the reads files
+    fn write(&mut self, variant: &vcf::Record, evidences: &[(String, VariantEvidence)]) {
+        for (sample, evidence) in evidences {
+            if let Some(writer) = self.table.as_mut() {
+                write_table_rows(writer, variant, sample, evidence);
+            }
+            // only single base substitutions go to the artefacts summary
+            if let Some(summary) = self.artefacts.as_mut() {
+                if variant.reference_bases().len() == 1 {
+                    let context = variant_context(variant, &self.ms_key);
+                    for (idx, allele) in variant.alternate_bases().iter().enumerate() {
+                        summary.add(sample, &context, allele.to_string().as_bytes(), &evidence.alt_reads(idx));
+                    }
+                }
+            }
+        }
+        if let Some(output) = self.vcf.as_mut() {
+            let mut record_out = variant.clone();
+            self.sample_filter.subset_record(&mut record_out, self.sample_indices);
+            annotate_record(&mut record_out, evidences, &output.columns, output.header.sample_names().len());
+            output.writer.write_record(&output.header, &record_out).unwrap();
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn readinfo(reads: ReadsInput, variants_in: PathBuf, output: ReadinfoOutput, key_name: String, read_position: ReadPosition, read_filter: ReadFilter, sample_filter: SampleFilter, streaming: bool, fragments: bool, threads: usize) {
 
     // block to define the variant reader
     let vcf_path: PathBuf = variants_in;
-    let mut variants_reader = vcf::reader::Builder::default()
+    let mut variants_reader = vcf::reader::Builder
     .build_from_path(vcf_path)
     .unwrap();
     let variants_header: vcf::Header = variants_reader.read_header().unwrap();
+    // sites only vcf files use all the variants
+    let sample_indices = sample_filter.sample_indices(&variants_header);
+    if sample_filter.is_active() && sample_indices.is_empty() {
+        panic!("The vcf has no samples, sample options can't be used");
+    }
 
-    // a block to define the bam reader
-    let bam_path = reads;
-    let mut bam_reader: bam::IndexedReader<noodles::bgzf::Reader<std::fs::File>> = bam::indexed_reader::Builder::default()
-        .build_from_path(bam_path).unwrap();
-    let bam_header: sam::Header = bam_reader.read_header().unwrap();
-
-    let mut hash_readpos: HashMap<usize, u32> = HashMap::new(); 
-
-    for result in variants_reader.records(&variants_header){
-        let variant = result.unwrap();
-        get_readinfo_from_record(
-            &variant,
-            &mut bam_reader,
-            &bam_header,
-            &mut hash_readpos,
-        );
+    let table_writer = output.table.map(|path| {
+        let mut writer = BufWriter::new(File::create(path).unwrap());
+        writeln!(writer, "{}", TABLE_HEADER).unwrap();
+        writer
+    });
+
+    let vcf_writer = output.vcf.map(|path| {
+        let mut writer = vcf::writer::Builder.build_from_path(path).unwrap();
+        let mut header = sample_filter.subset_header(&variants_header);
+        let columns = add_format_headers(&mut header, &reads.labels());
+        writer.write_header(&header).unwrap();
+        VcfOutput { writer, header, columns }
+    });
+
+    let mut writers = EvidenceWriters {
+        table: table_writer,
+        vcf: vcf_writer,
+        artefacts: output.artefacts.as_ref().map(|_| ArtefactSummary::default()),
+        ms_key: key_name.parse().unwrap(),
+        sample_filter: &sample_filter,
+        sample_indices: &sample_indices,
+    };
+
+    let variants = variants_reader
+        .records(&variants_header)
+        .map(|result| result.unwrap())
+        .filter(|variant| sample_filter.passes_record(variant, &sample_indices));
+
+    let chunks = VariantChunks {
+        variants,
+        streaming,
+        last: None,
+        finished_contigs: HashSet::new(),
+        pending: None,
+    };
+
+    let totals = process_chunks(
+        chunks,
+        threads,
+        || {
+            let worker = ReadinfoReaders::open(&reads);
+            let histograms = worker
+                .samples
+                .iter()
+                .map(|sample| (sample.label.clone(), SampleHistograms::default()))
+                .collect();
+            let outcomes = worker
+                .samples
+                .iter()
+                .map(|sample| (sample.label.clone(), ReadOutcomes::default()))
+                .collect();
+            (worker, (histograms, outcomes))
+        },
+        |worker, (histograms, outcomes), chunk| {
+            chunk_evidence(chunk, worker, streaming, &read_position, &read_filter, fragments, histograms, outcomes)
+        },
+        |chunk, evidences| {
+            for (variant, variant_evidences) in chunk.iter().zip(&evidences) {
+                writers.write(variant, variant_evidences);
+            }
+        },
+    );
+
+    let mut histograms: BTreeMap<String, SampleHistograms> = BTreeMap::new();
+    // reads seen by several variants or threads are counted once per sample
+    let mut outcomes: BTreeMap<String, ReadOutcomes> = BTreeMap::new();
+    for (worker_histograms, worker_outcomes) in totals {
+        for (label, sample_histograms) in worker_histograms {
+            histograms.entry(label).or_default().merge(sample_histograms);
+        }
+        for (label, sample_outcomes) in worker_outcomes {
+            outcomes.entry(label).or_default().merge(sample_outcomes);
+        }
     }
+    let mut filter_counts = FilterCounts::default();
+    for sample_outcomes in outcomes.values() {
+        filter_counts.merge(sample_outcomes.counts());
+    }
+
+    filter_counts.report();
 
-    let json = serde_json::to_string(&hash_readpos).unwrap();
-    let mut file = File::create(json_out).unwrap();
+    if let (Some(path), Some(summary)) = (output.artefacts, writers.artefacts.as_ref()) {
+        let mut writer = BufWriter::new(File::create(path).unwrap());
+        summary.write_tsv(&mut writer).unwrap();
+    }
+
+    let json = serde_json::to_string(&histograms).unwrap();
+    let mut file = File::create(output.json).unwrap();
     file.write_all(json.as_bytes()).unwrap();
 
 }
 
+// chunks of consecutive variants of the same contig, processed by the
+// threads and written back in order. they are built as the threads need
+// them, checking that the variants are sorted for --streaming
+struct VariantChunks<I: Iterator<Item = vcf::Record>> {
+    variants: I,
+    streaming: bool,
+    // contig and position of the last variant read
+    last: Option<(String, vcf::record::Position)>,
+    finished_contigs: HashSet<String>,
+    // first variant of the next chunk, in another contig
+    pending: Option<vcf::Record>,
+}
+
+impl<I: Iterator<Item = vcf::Record>> VariantChunks<I> {
+    // next variant, and if it is in the same contig as the previous one
+    fn next_variant(&mut self) -> Option<(vcf::Record, bool)> {
+        let variant = self.variants.next()?;
+        let chrom = variant.chromosome().to_string();
+        let same_contig = match &self.last {
+            Some((last_chrom, last_position)) if *last_chrom == chrom => {
+                if self.streaming && variant.position() < *last_position {
+                    panic!("Problem with the variants: {}:{} is not sorted, --streaming needs a sorted vcf", chrom, variant.position());
+                }
+                true
+            },
+            Some((last_chrom, _)) => {
+                self.finished_contigs.insert(last_chrom.clone());
+                false
+            },
+            None => false,
+        };
+        if self.streaming && !same_contig && self.finished_contigs.contains(&chrom) {
+            panic!("Problem with the variants: {} is not sorted, --streaming needs a sorted vcf", chrom);
+        }
+        self.last = Some((chrom, variant.position()));
+        Some((variant, same_contig))
+    }
+
+    // true if the variant (of the same contig) goes in the chunk
+    fn continues(&self, chunk: &[vcf::Record], variant: &vcf::Record) -> bool {
+        if chunk.len() < VARIANTS_PER_CHUNK {
+            return true;
+        }
+        let gap = match chunk.last() {
+            Some(last) => usize::from(variant.position()).saturating_sub(usize::from(last.position())),
+            None => 0,
+        };
+        self.streaming && chunk.len() < STREAMING_CHUNK_LIMIT && gap <= STREAMING_CHUNK_GAP
+    }
+}
+
+impl<I: Iterator<Item = vcf::Record>> Iterator for VariantChunks<I> {
+    type Item = Vec<vcf::Record>;
 
-fn get_readinfo_from_record(
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = match self.pending.take() {
+            Some(variant) => variant,
+            None => self.next_variant()?.0,
+        };
+        let mut chunk = vec![first];
+        while let Some((variant, same_contig)) = self.next_variant() {
+            if !same_contig || !self.continues(&chunk, &variant) {
+                self.pending = Some(variant);
+                break;
+            }
+            chunk.push(variant);
+        }
+        Some(chunk)
+    }
+}
+
+// readers of each thread: bam, cram or sam readers, one for each sample,
+// and the reference genome (if any) to count the mismatches of the reads
+// without NM tag
+struct ReadinfoReaders {
+    samples: Vec<SampleReads>,
+    reference: Option<ReferenceCache>,
+}
+
+impl ReadinfoReaders {
+    fn open(reads: &ReadsInput) -> Self {
+        let reference = reads.reference.as_ref().map(|path| {
+            let reader = fasta::indexed_reader::Builder::default()
+                .build_from_path(path)
+                .unwrap();
+            ReferenceCache::new(reader, REFERENCE_WINDOW)
+        });
+        ReadinfoReaders { samples: reads.open(), reference }
+    }
+}
+
+// evidence of each variant of a chunk in every sample, with the label of
+// the sample
+#[allow(clippy::too_many_arguments)]
+fn chunk_evidence(
+    chunk: &[vcf::Record],
+    readers: &mut ReadinfoReaders,
+    streaming: bool,
+    read_position: &ReadPosition,
+    read_filter: &ReadFilter,
+    fragments: bool,
+    histograms: &mut BTreeMap<String, SampleHistograms>,
+    outcomes: &mut BTreeMap<String, ReadOutcomes>,
+) -> Vec<Vec<(String, VariantEvidence)>> {
+    let ReadinfoReaders { samples, reference } = readers;
+    let mut evidences: Vec<Vec<(String, VariantEvidence)>> = chunk.iter().map(|_| Vec::with_capacity(samples.len())).collect();
+    for sample in samples.iter_mut() {
+        let sample_histograms = histograms.get_mut(&sample.label).unwrap();
+        let filter_counts = outcomes.get_mut(&sample.label).unwrap();
+        let sample_evidences = if streaming {
+            get_readinfo_streaming(chunk, sample.reader.as_mut(), reference.as_mut(), read_position, read_filter, fragments, sample_histograms, filter_counts)
+        } else {
+            chunk
+                .iter()
+                .map(|variant| get_readinfo_from_record(variant, sample.reader.as_mut(), reference.as_mut(), read_position, read_filter, fragments, sample_histograms, filter_counts))
+                .collect()
+        };
+        for (variant_evidences, evidence) in evidences.iter_mut().zip(sample_evidences) {
+            variant_evidences.push((sample.label.clone(), evidence));
+        }
+    }
+    evidences
+}
+
+// alleles of a variant as bytes, symbolic alleles are kept as text, so no
+// read matches them
+struct VariantAlleles {
+    position: usize,
+    reference: Vec<u8>,
+    alternates: Vec<Vec<u8>>,
+}
+
+impl VariantAlleles {
+    fn new(variant: &vcf::Record) -> Self {
+        VariantAlleles {
+            position: usize::from(variant.position()),
+            reference: variant.reference_bases().to_string().into_bytes(),
+            alternates: variant
+                .alternate_bases()
+                .iter()
+                .map(|allele| allele.to_string().into_bytes())
+                .collect(),
+        }
+    }
+}
+
+// allele of a read that passed the read level filters, None if the read is
+// not informative or its bases don't pass the filters
+fn read_allele(
+    read: &FilteredRead,
+    alleles: &VariantAlleles,
+    read_filter: &ReadFilter,
+    filter_counts: &mut ReadOutcomes,
+) -> Option<ReadAllele> {
+    let record = &read.record;
+    // reads that end (or start) within the reference allele are not informative
+    let allele = classify_read(record, alleles.position, &alleles.reference, &alleles.alternates)?;
+    let read_indices: Vec<usize> = allele.read_index.into_iter().collect();
+    if !read_filter.passes_bases(record, &read_indices, filter_counts) {
+        return None;
+    }
+    Some(allele)
+}
+
+// base quality of the read at the variant
+fn allele_quality(read: &FilteredRead, allele: &ReadAllele) -> Option<u8> {
+    allele
+        .read_index
+        .and_then(|idx| read.record.quality_scores().as_ref().get(idx))
+        .map(|score| u8::from(*score))
+}
+
+// adds the reads of a variant to its evidence, by fragment the overlapping
+// mates of a pair count once: the mate with the higher base quality at the
+// variant is kept and, if they support different alleles, the pair is
+// discordant (an allele of other with the same base quality)
+fn add_reads(
+    observations: Vec<(&FilteredRead, ReadAllele)>,
+    fragments: bool,
+    read_position: &ReadPosition,
+    histograms: &mut SampleHistograms,
+    evidence: &mut VariantEvidence,
+) {
+    let observations = if fragments {
+        let mut discordant_pairs = 0;
+        let merged = merge_mates(
+            observations,
+            |(read, _)| mate_key(&read.record),
+            |first, second| {
+                let first_quality = allele_quality(first.0, &first.1);
+                let second_quality = allele_quality(second.0, &second.1);
+                let (mut kept, other) = if second_quality > first_quality { (second, first) } else { (first, second) };
+                if kept.1.support != other.1.support {
+                    discordant_pairs += 1;
+                    if first_quality == second_quality {
+                        kept.1.support = AlleleSupport::Other;
+                    }
+                }
+                kept
+            },
+        );
+        evidence.discordant_pairs = Some(discordant_pairs);
+        merged
+    } else {
+        observations
+    };
+    for (read, allele) in observations {
+        add_read(read, allele, read_position, histograms, evidence);
+    }
+}
+
+// adds a read to the evidence of a variant
+fn add_read(
+    read: &FilteredRead,
+    allele: ReadAllele,
+    read_position: &ReadPosition,
+    histograms: &mut SampleHistograms,
+    evidence: &mut VariantEvidence,
+) {
+    let record = &read.record;
+    // reads with the whole allele deleted have no position
+    let position = allele.read_index.map(|read_index| {
+        let (cycle, read_length) = read_cycle(record, read_index);
+        read_position.value(cycle, read_length)
+    });
+    if let Some(position) = position {
+        histograms.position.add(allele.support, position);
+    }
+    if let Some(length) = fragment_length(record) {
+        histograms.fragment_length.add(allele.support, length);
+    }
+    if let Some(score) = allele_quality(read, &allele) {
+        histograms.base_quality.add(allele.support, score as usize);
+    }
+    if let Some(mapq) = record.mapping_quality() {
+        histograms.mapping_quality.add(allele.support, u8::from(mapq) as usize);
+    }
+    if let Some(mismatches) = read.mismatches {
+        histograms.mismatches.add(allele.support, mismatches);
+    }
+    evidence.add(read, allele, position);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn get_readinfo_from_record<R: AlignmentQuery + ?Sized>(
     variant: &vcf::Record,
-    bam_reader: &mut bam::IndexedReader<noodles::bgzf::Reader<std::fs::File>>,
-    bam_header: &sam::Header,
-    hash_ref: &mut HashMap<usize, u32>,
-) -> () {
-    let variant_position = variant.position();
-    let variant_position_usize = usize::from(variant_position);
+    reads_reader: &mut R,
+    mut reference: Option<&mut ReferenceCache>,
+    read_position: &ReadPosition,
+    read_filter: &ReadFilter,
+    fragments: bool,
+    histograms: &mut SampleHistograms,
+    filter_counts: &mut ReadOutcomes,
+) -> VariantEvidence {
+    let alleles = VariantAlleles::new(variant);
+    let mut evidence = VariantEvidence::new(alleles.alternates.len());
+
+    let chrom = variant.chromosome().to_string();
     let region = fromvcfrecord2region(variant);
-    let query = bam_reader.query(bam_header, &region).unwrap();
+    let query = reads_reader.query(&region).unwrap();
 
+    let mut reads: Vec<FilteredRead> = Vec::new();
     for result in query {
         let record = result.unwrap();
-        let alig_start = record.alignment_start().unwrap();
-        // let alig_end  = record.alignment_end().unwrap();
-        let pos_start = usize::from(alig_start);
-        let size_to_start = variant_position_usize - pos_start;
-
-        let stat = hash_ref.entry(size_to_start).or_insert(0);
-        *stat += 1;
+        if !read_filter.passes_read(&record, filter_counts) {
+            continue;
+        }
+        reads.push(FilteredRead::new(record, &chrom, reference.as_deref_mut()));
     }
+    let observations = reads
+        .iter()
+        .filter_map(|read| read_allele(read, &alleles, read_filter, filter_counts).map(|allele| (read, allele)))
+        .collect();
+    add_reads(observations, fragments, read_position, histograms, &mut evidence);
+    evidence
 }
 
+// sweep line over sorted variants of one contig and their reads: each read
+// is decoded and filtered once, and kept in the window while it can still
+// overlap the next variants
+#[allow(clippy::too_many_arguments)]
+fn get_readinfo_streaming<R: AlignmentQuery + ?Sized>(
+    variants: &[vcf::Record],
+    reads_reader: &mut R,
+    mut reference: Option<&mut ReferenceCache>,
+    read_position: &ReadPosition,
+    read_filter: &ReadFilter,
+    fragments: bool,
+    histograms: &mut SampleHistograms,
+    filter_counts: &mut ReadOutcomes,
+) -> Vec<VariantEvidence> {
+    let (first, last) = match (variants.first(), variants.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Vec::new(),
+    };
+    let start = Position::try_from(usize::from(first.position())).unwrap();
+    let end = Position::try_from(usize::from(last.position())).unwrap();
+    let chrom = first.chromosome().to_string();
+    let region = Region::new(chrom.clone(), start..=end);
+    let mut query = reads_reader.query(&region).unwrap().peekable();
+    let mut window: VecDeque<FilteredRead> = VecDeque::new();
+    let mut evidences = Vec::with_capacity(variants.len());
+
+    for variant in variants {
+        let alleles = VariantAlleles::new(variant);
+        // reads starting after the variant can't cover it, they stay in the query
+        while let Some(result) = query.next_if(|result| match result {
+            Ok(record) => record.alignment_start().is_none_or(|start| usize::from(start) <= alleles.position),
+            Err(_) => true,
+        }) {
+            let record = result.unwrap();
+            if read_filter.passes_read(&record, filter_counts) {
+                window.push_back(FilteredRead::new(record, &chrom, reference.as_deref_mut()));
+            }
+        }
+        window.retain(|read| read.record.alignment_end().is_some_and(|end| usize::from(end) >= alleles.position));
+
+        let mut evidence = VariantEvidence::new(alleles.alternates.len());
+        let observations = window
+            .iter()
+            .filter_map(|read| read_allele(read, &alleles, read_filter, filter_counts).map(|allele| (read, allele)))
+            .collect();
+        add_reads(observations, fragments, read_position, histograms, &mut evidence);
+        evidences.push(evidence);
+    }
+    evidences
+}
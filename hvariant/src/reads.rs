@@ -0,0 +1,301 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use noodles::bam;
+use noodles::bgzf;
+use noodles::core::Region;
+use noodles::cram;
+use noodles::fasta;
+use noodles::sam;
+
+// alignment files for the read based commands. bam, cram and sam are
+// detected from the content of the file and queried by region through the
+// same trait, records are always converted to sam alignment records.
+
+pub type Records<'a> = Box<dyn Iterator<Item = io::Result<sam::alignment::Record>> + 'a>;
+
+pub trait AlignmentQuery {
+    fn header(&self) -> &sam::Header;
+    /// Records that overlap the region.
+    fn query<'a>(&'a mut self, region: &Region) -> io::Result<Records<'a>>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlignmentFormat {
+    Bam,
+    Cram,
+    /// bgzipped sam, needs an index
+    BgzfSam,
+    Sam,
+}
+
+impl AlignmentFormat {
+    /// Guesses the format from the magic numbers of the file.
+    pub fn from_content(path: &Path) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let magic = reader.fill_buf()?;
+        if magic.starts_with(b"CRAM") {
+            return Ok(AlignmentFormat::Cram);
+        }
+        if !magic.starts_with(&[0x1f, 0x8b]) {
+            return Ok(AlignmentFormat::Sam);
+        }
+        let mut block_magic = [0; 4];
+        let mut bgzf_reader = bgzf::Reader::new(reader);
+        match bgzf_reader.read_exact(&mut block_magic) {
+            Ok(()) if &block_magic == b"BAM\x01" => Ok(AlignmentFormat::Bam),
+            _ => Ok(AlignmentFormat::BgzfSam),
+        }
+    }
+}
+
+pub struct BamReads {
+    reader: bam::IndexedReader<bgzf::Reader<File>>,
+    header: sam::Header,
+}
+
+impl AlignmentQuery for BamReads {
+    fn header(&self) -> &sam::Header {
+        &self.header
+    }
+
+    fn query<'a>(&'a mut self, region: &Region) -> io::Result<Records<'a>> {
+        Ok(Box::new(self.reader.query(&self.header, region)?))
+    }
+}
+
+pub struct CramReads {
+    reader: cram::IndexedReader<File>,
+    header: sam::Header,
+}
+
+impl AlignmentQuery for CramReads {
+    fn header(&self) -> &sam::Header {
+        &self.header
+    }
+
+    fn query<'a>(&'a mut self, region: &Region) -> io::Result<Records<'a>> {
+        let header = &self.header;
+        let query = self.reader.query(header, region)?;
+        Ok(Box::new(query.map(move |result| result.and_then(|record| record.try_into_alignment_record(header)))))
+    }
+}
+
+pub struct BgzfSamReads {
+    reader: sam::IndexedReader<File>,
+    header: sam::Header,
+}
+
+impl AlignmentQuery for BgzfSamReads {
+    fn header(&self) -> &sam::Header {
+        &self.header
+    }
+
+    fn query<'a>(&'a mut self, region: &Region) -> io::Result<Records<'a>> {
+        Ok(Box::new(self.reader.query(&self.header, region)?))
+    }
+}
+
+/// Plain sam files can't be indexed, the records are kept in memory (sorted
+/// by position) and each query binary searches the records that can overlap
+/// the region, those that start at most the longest alignment before it.
+pub struct SamReads {
+    records: Vec<sam::alignment::Record>,
+    header: sam::Header,
+    // longest reference span of the records
+    max_span: usize,
+}
+
+impl AlignmentQuery for SamReads {
+    fn header(&self) -> &sam::Header {
+        &self.header
+    }
+
+    fn query<'a>(&'a mut self, region: &Region) -> io::Result<Records<'a>> {
+        let reference_id = match self.header.reference_sequences().get_index_of(region.name()) {
+            Some(id) => id,
+            None => return Ok(Box::new(std::iter::empty())),
+        };
+        let interval = region.interval();
+        let region_start = interval.start().map(usize::from).unwrap_or(1);
+        let region_end = interval.end().map(usize::from).unwrap_or(usize::MAX);
+        let window_start = region_start.saturating_sub(self.max_span);
+        let key = |record: &sam::alignment::Record| {
+            (record.reference_sequence_id(), record.alignment_start().map(usize::from))
+        };
+        let first = self.records.partition_point(|record| key(record) < (Some(reference_id), Some(window_start)));
+        let last = self.records.partition_point(|record| key(record) <= (Some(reference_id), Some(region_end)));
+        let reference_id = Some(reference_id);
+        let overlapping = self.records[first..last.max(first)].iter().filter(move |record| {
+            match (record.reference_sequence_id(), record.alignment_start(), record.alignment_end()) {
+                (Some(id), Some(start), Some(end)) => Some(id) == reference_id && interval.intersects((start..=end).into()),
+                _ => false,
+            }
+        });
+        Ok(Box::new(overlapping.cloned().map(Ok)))
+    }
+}
+
+/// Alignment files given to a command, with optional sample labels, and
+/// the reference genome to decode them.
+pub struct ReadsInput {
+    pub paths: Vec<PathBuf>,
+    pub labels: Vec<String>,
+    pub reference: Option<PathBuf>,
+}
+
+/// Reads of one sample.
+pub struct SampleReads {
+    pub label: String,
+    pub reader: Box<dyn AlignmentQuery>,
+}
+
+impl ReadsInput {
+    /// Labels of the reads files, as in `open`, reading only the headers.
+    pub fn labels(&self) -> Vec<String> {
+        let labels: Vec<String> = self
+            .paths
+            .iter()
+            .enumerate()
+            .map(|(idx, path)| match self.labels.get(idx) {
+                Some(label) => label.clone(),
+                None => {
+                    let header = read_header(path)
+                        .unwrap_or_else(|e| panic!("Problem reading the header of {:?}: {:?}", path, e));
+                    sample_name(&header, path)
+                },
+            })
+            .collect();
+        self.check_labels(&labels);
+        labels
+    }
+
+    /// Names of the reference sequences in the order of the headers (of the
+    /// first file, then those only in the others), reading only the headers.
+    pub fn contigs(&self) -> Vec<String> {
+        let mut contigs: Vec<String> = Vec::new();
+        for path in &self.paths {
+            let header = read_header(path)
+                .unwrap_or_else(|e| panic!("Problem reading the header of {:?}: {:?}", path, e));
+            for name in header.reference_sequences().keys() {
+                let name = name.to_string();
+                if !contigs.contains(&name) {
+                    contigs.push(name);
+                }
+            }
+        }
+        contigs
+    }
+
+    /// Opens every file, the label is the one given, the SM of the read
+    /// groups or the file name, in that order. Labels must be unique.
+    pub fn open(&self) -> Vec<SampleReads> {
+        let samples: Vec<SampleReads> = self
+            .paths
+            .iter()
+            .enumerate()
+            .map(|(idx, path)| {
+                let reader = open_reads(path, self.reference.as_deref());
+                let label = match self.labels.get(idx) {
+                    Some(label) => label.clone(),
+                    None => sample_name(reader.header(), path),
+                };
+                SampleReads { label, reader }
+            })
+            .collect();
+        let labels: Vec<String> = samples.iter().map(|sample| sample.label.clone()).collect();
+        self.check_labels(&labels);
+        samples
+    }
+
+    fn check_labels(&self, labels: &[String]) {
+        if !self.labels.is_empty() && self.labels.len() != self.paths.len() {
+            panic!("Problem with the sample labels: {} labels for {} reads files", self.labels.len(), self.paths.len());
+        }
+        for (idx, label) in labels.iter().enumerate() {
+            if labels[..idx].contains(label) {
+                panic!("Problem with the sample labels: {} is repeated, use --labels", label);
+            }
+        }
+    }
+}
+
+// header of an alignment file, without the index or the reference genome
+fn read_header(path: &Path) -> io::Result<sam::Header> {
+    let file = File::open(path)?;
+    match AlignmentFormat::from_content(path)? {
+        AlignmentFormat::Bam => bam::Reader::new(file).read_header(),
+        AlignmentFormat::Cram => cram::Reader::new(file).read_header(),
+        AlignmentFormat::BgzfSam => sam::Reader::new(bgzf::Reader::new(file)).read_header(),
+        AlignmentFormat::Sam => sam::Reader::new(BufReader::new(file)).read_header(),
+    }
+}
+
+// sample of the reads, from the read groups or the file name
+fn sample_name(header: &sam::Header, path: &Path) -> String {
+    match header.read_groups().values().find_map(|read_group| read_group.sample()) {
+        Some(sample) => sample.to_string(),
+        None => path
+            .file_stem()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+/// Opens an alignment file of any format, cram files need the reference
+/// genome (indexed fasta) to decode the sequences.
+pub fn open_reads(path: &Path, reference: Option<&Path>) -> Box<dyn AlignmentQuery> {
+    let format = AlignmentFormat::from_content(path)
+        .unwrap_or_else(|e| panic!("Problem opening the reads file {:?}: {:?}", path, e));
+    match format {
+        AlignmentFormat::Bam => {
+            let mut reader = bam::indexed_reader::Builder::default()
+                .build_from_path(path)
+                .unwrap();
+            let header = reader.read_header().unwrap();
+            Box::new(BamReads { reader, header })
+        },
+        AlignmentFormat::Cram => {
+            let reference = match reference {
+                Some(reference) => reference,
+                None => panic!("Problem reading {:?}: cram files need the reference genome (--reference)", path),
+            };
+            let fasta_reader = fasta::indexed_reader::Builder::default()
+                .build_from_path(reference)
+                .unwrap();
+            let repository = fasta::Repository::new(fasta::repository::adapters::IndexedReader::new(fasta_reader));
+            let mut reader = cram::indexed_reader::Builder::default()
+                .set_reference_sequence_repository(repository)
+                .build_from_path(path)
+                .unwrap();
+            let header = reader.read_header().unwrap();
+            Box::new(CramReads { reader, header })
+        },
+        AlignmentFormat::BgzfSam => {
+            let mut reader = sam::indexed_reader::Builder::default()
+                .build_from_path(path)
+                .unwrap();
+            let header = reader.read_header().unwrap();
+            Box::new(BgzfSamReads { reader, header })
+        },
+        AlignmentFormat::Sam => {
+            let mut reader = sam::reader::Builder
+                .build_from_path(path)
+                .unwrap();
+            let header = reader.read_header().unwrap();
+            let mut records = reader
+                .records(&header)
+                .collect::<io::Result<Vec<_>>>()
+                .unwrap_or_else(|e| panic!("Problem reading the records of {:?}: {:?}", path, e));
+            // queries return the records by position, as with indexed files
+            records.sort_by_key(|record| (record.reference_sequence_id(), record.alignment_start()));
+            let max_span = records
+                .iter()
+                .filter_map(|record| Some(usize::from(record.alignment_end()?) - usize::from(record.alignment_start()?) + 1))
+                .max()
+                .unwrap_or(0);
+            Box::new(SamReads { records, header, max_span })
+        },
+    }
+}
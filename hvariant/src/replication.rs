@@ -0,0 +1,248 @@
+
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+use noodles::bed;
+
+use varianth_core::sequence::is_pyrimidine;
+
+// intervals are kept as noodles returns them, 1-based and closed,
+// so they can be compared directly with vcf positions.
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ForkDirection {
+    /// fork moving towards increasing coordinates
+    Rightward,
+    /// fork moving towards decreasing coordinates
+    Leftward,
+}
+
+struct Interval<T> {
+    start: usize,
+    end: usize,
+    value: T,
+}
+
+fn read_bed_intervals<T, F>(path: PathBuf, parse_value: F) -> HashMap<String, Vec<Interval<T>>>
+where
+    F: Fn(&bed::Record<3>) -> Option<T>,
+{
+    let bed_file = match File::open(&path) {
+        Ok(file) => BufReader::new(file),
+        Err(error) => panic!("Problem opening the file {:?}: {:?}", path, error),
+    };
+    let mut bed_reader = bed::Reader::new(bed_file);
+
+    let mut intervals: HashMap<String, Vec<Interval<T>>> = HashMap::new();
+    for result in bed_reader.records::<3>() {
+        let record = match result {
+            Ok(record) => record,
+            Err(error) => panic!("Problem reading the file {:?}: {:?}", path, error),
+        };
+        let value = match parse_value(&record) {
+            Some(value) => value,
+            None => panic!("Problem parsing the record {} in {:?}", record, path),
+        };
+        intervals
+            .entry(record.reference_sequence_name().to_string())
+            .or_default()
+            .push(Interval {
+                start: usize::from(record.start_position()),
+                end: usize::from(record.end_position()),
+                value,
+            });
+    }
+
+    for chrom_intervals in intervals.values_mut() {
+        chrom_intervals.sort_by_key(|x| x.start);
+    }
+    intervals
+}
+
+// index of the interval that contains pos, intervals are expected to be
+// sorted and not overlapping
+fn find_interval<T>(intervals: &[Interval<T>], pos: usize) -> Option<usize> {
+    let idx = intervals.partition_point(|x| x.start <= pos).checked_sub(1)?;
+    if intervals[idx].end >= pos {
+        Some(idx)
+    } else {
+        None
+    }
+}
+
+/// Replication timing profile read from a bedGraph file
+/// (chrom, start, end, value).
+pub struct ReplicationTiming {
+    intervals: HashMap<String, Vec<Interval<f64>>>,
+    breaks: Vec<f64>,
+}
+
+impl ReplicationTiming {
+    /// Reads the bedGraph and splits its values in `n_bins` quantile bins.
+    pub fn from_bedgraph(path: PathBuf, n_bins: usize) -> Self {
+        let intervals = read_bed_intervals(path, |record| {
+            record.optional_fields().first()?.parse::<f64>().ok()
+        });
+
+        let mut values: Vec<f64> = intervals
+            .values()
+            .flat_map(|x| x.iter().map(|iv| iv.value))
+            .filter(|x| x.is_finite())
+            .collect();
+        values.sort_by(|a, b| a.total_cmp(b));
+
+        let breaks = if values.is_empty() {
+            Vec::new()
+        } else {
+            (1..n_bins)
+                .map(|i| values[i * values.len() / n_bins])
+                .collect()
+        };
+
+        Self { intervals, breaks }
+    }
+
+    pub fn value(&self, chrom: &str, pos: usize) -> Option<f64> {
+        let intervals = self.intervals.get(chrom)?;
+        let idx = find_interval(intervals, pos)?;
+        Some(intervals[idx].value)
+    }
+
+    /// Timing bin of a position, bin 1 holds the lowest values of the
+    /// profile (late replication in the usual early/late log ratio).
+    pub fn bin(&self, chrom: &str, pos: usize) -> Option<usize> {
+        let value = self.value(chrom, pos)?;
+        Some(self.breaks.partition_point(|x| *x <= value) + 1)
+    }
+
+    /// Fork direction inferred from the slope of the timing profile,
+    /// forks move from early (high values) to late (low values) regions.
+    pub fn direction(&self, chrom: &str, pos: usize) -> Option<ForkDirection> {
+        let intervals = self.intervals.get(chrom)?;
+        let idx = find_interval(intervals, pos)?;
+        let previous = intervals[idx.checked_sub(1)?].value;
+        let next = intervals.get(idx + 1)?.value;
+        if next < previous {
+            Some(ForkDirection::Rightward)
+        } else if next > previous {
+            Some(ForkDirection::Leftward)
+        } else {
+            None
+        }
+    }
+}
+
+/// Fork direction track, a BED6 file where the strand column is `+` for
+/// rightward moving forks and `-` for leftward moving forks.
+pub struct ForkTrack {
+    intervals: HashMap<String, Vec<Interval<ForkDirection>>>,
+}
+
+impl ForkTrack {
+    pub fn from_bed(path: PathBuf) -> Self {
+        // name and score are not used, so they can hold anything
+        let intervals = read_bed_intervals(path, |record| {
+            match record.optional_fields().get(2).map(|x| x.as_str()) {
+                Some("+") => Some(ForkDirection::Rightward),
+                Some("-") => Some(ForkDirection::Leftward),
+                _ => None,
+            }
+        });
+        Self { intervals }
+    }
+
+    pub fn direction(&self, chrom: &str, pos: usize) -> Option<ForkDirection> {
+        let intervals = self.intervals.get(chrom)?;
+        let idx = find_interval(intervals, pos)?;
+        Some(intervals[idx].value)
+    }
+}
+
+/// Replication origins track (BED3). A position is assumed to be
+/// replicated by the fork coming from the closest origin.
+pub struct OriginTrack {
+    intervals: HashMap<String, Vec<Interval<()>>>,
+}
+
+impl OriginTrack {
+    pub fn from_bed(path: PathBuf) -> Self {
+        let intervals = read_bed_intervals(path, |_| Some(()));
+        Self { intervals }
+    }
+
+    pub fn direction(&self, chrom: &str, pos: usize) -> Option<ForkDirection> {
+        let intervals = self.intervals.get(chrom)?;
+        let idx = intervals.partition_point(|x| x.start <= pos);
+        // positions inside an origin have no defined direction
+        let left_distance = match idx.checked_sub(1) {
+            Some(i) if intervals[i].end >= pos => return None,
+            Some(i) => Some(pos - intervals[i].end),
+            None => None,
+        };
+        let right_distance = intervals.get(idx).map(|x| x.start - pos);
+
+        match (left_distance, right_distance) {
+            (Some(left), Some(right)) if left < right => Some(ForkDirection::Rightward),
+            (Some(left), Some(right)) if left > right => Some(ForkDirection::Leftward),
+            (Some(_), None) => Some(ForkDirection::Rightward),
+            (None, Some(_)) => Some(ForkDirection::Leftward),
+            _ => None,
+        }
+    }
+}
+
+/// Replication strand of a variant given the reference base and the
+/// direction of the fork.
+///
+/// Follows the pyrimidine convention used for the mutation subtypes:
+/// with a rightward fork the reference (+) strand is the lagging strand
+/// template, so C/T reference bases are lagging and G/A (whose pyrimidine
+/// is on the - strand) are leading. Leftward forks are the opposite.
+pub fn replication_strand(ref_base: u8, direction: ForkDirection) -> &'static str {
+    match (is_pyrimidine(ref_base), direction) {
+        (true, ForkDirection::Rightward) | (false, ForkDirection::Leftward) => "lagging",
+        (false, ForkDirection::Rightward) | (true, ForkDirection::Leftward) => "leading",
+    }
+}
+
+/// Collects the replication tracks used to annotate variants, the strand
+/// is taken from the forks track, then the origins and finally the slope
+/// of the timing profile.
+#[derive(Default)]
+pub struct ReplicationAnnotator {
+    pub timing: Option<ReplicationTiming>,
+    pub forks: Option<ForkTrack>,
+    pub origins: Option<OriginTrack>,
+}
+
+impl ReplicationAnnotator {
+    pub fn has_strand(&self) -> bool {
+        self.forks.is_some() || self.origins.is_some() || self.timing.is_some()
+    }
+
+    pub fn has_timing(&self) -> bool {
+        self.timing.is_some()
+    }
+
+    pub fn direction(&self, chrom: &str, pos: usize) -> Option<ForkDirection> {
+        if let Some(forks) = &self.forks {
+            return forks.direction(chrom, pos);
+        }
+        if let Some(origins) = &self.origins {
+            return origins.direction(chrom, pos);
+        }
+        self.timing.as_ref()?.direction(chrom, pos)
+    }
+
+    pub fn strand(&self, chrom: &str, pos: usize, ref_base: u8) -> Option<&'static str> {
+        let direction = self.direction(chrom, pos)?;
+        Some(replication_strand(ref_base, direction))
+    }
+
+    pub fn timing_bin(&self, chrom: &str, pos: usize) -> Option<usize> {
+        self.timing.as_ref()?.bin(chrom, pos)
+    }
+}
@@ -0,0 +1,204 @@
+
+
+use noodles::vcf;
+use noodles::vcf::record::genotypes::keys::key;
+use noodles::vcf::record::genotypes::sample::value::Array;
+use noodles::vcf::record::genotypes::sample::Value;
+use noodles::vcf::record::genotypes::{Genotypes, Sample};
+
+use crate::input::Variant;
+
+// genotype aware selection of samples, a sample carries an alternative
+// allele if its GT contains it and it passes the depth (DP, or the sum of
+// AD) and VAF (from AD) filters.
+
+pub struct SampleFilter {
+    pub samples: Option<Vec<String>>,
+    pub min_depth: u32,
+    pub min_vaf: f64,
+}
+
+impl SampleFilter {
+    /// True if a subset or any of the filters was requested.
+    pub fn is_active(&self) -> bool {
+        self.samples.is_some() || self.min_depth > 0 || self.min_vaf > 0.0
+    }
+
+    /// Index and name of the selected samples in the vcf header, all of
+    /// them if no subset was requested.
+    pub fn sample_indices(&self, header: &vcf::Header) -> Vec<(usize, String)> {
+        let sample_names = header.sample_names();
+        match &self.samples {
+            None => sample_names
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (i, name.clone()))
+                .collect(),
+            Some(samples) => samples
+                .iter()
+                .map(|name| match sample_names.get_index_of(name) {
+                    Some(i) => (i, name.clone()),
+                    None => panic!("Sample {} is not in the vcf header", name),
+                })
+                .collect(),
+        }
+    }
+
+    /// Subsets the header to the selected samples.
+    pub fn subset_header(&self, header: &vcf::Header) -> vcf::Header {
+        let mut header_out = header.clone();
+        if let Some(samples) = &self.samples {
+            header_out.sample_names_mut().retain(|x| samples.contains(x));
+        }
+        header_out
+    }
+
+    /// Subsets the genotypes of a record to the selected samples, the
+    /// indices need to be in the same order as the header.
+    pub fn subset_record(&self, record: &mut vcf::Record, indices: &[(usize, String)]) {
+        if self.samples.is_none() {
+            return;
+        }
+        let mut sorted_indices: Vec<usize> = indices.iter().map(|(i, _)| *i).collect();
+        sorted_indices.sort();
+
+        let genotypes = record.genotypes();
+        let values: Vec<Vec<Option<Value>>> = sorted_indices
+            .iter()
+            .filter_map(|i| genotypes.get_index(*i))
+            .map(|sample| sample.values().to_vec())
+            .collect();
+        *record.genotypes_mut() = Genotypes::new(genotypes.keys().clone(), values);
+    }
+
+    /// Names of the selected samples that carry the alternative allele
+    /// `allele` (1-based, as in GT).
+    pub fn carriers<'a>(&self, record: &vcf::Record, indices: &'a [(usize, String)], allele: usize) -> Vec<&'a str> {
+        let genotypes = record.genotypes();
+        indices
+            .iter()
+            .filter(|(i, _)| match genotypes.get_index(*i) {
+                Some(sample) => self.is_carrier(&sample, allele),
+                None => false,
+            })
+            .map(|(_, name)| name.as_str())
+            .collect()
+    }
+
+    /// True if any of the selected samples carries any alternative allele.
+    pub fn is_carried(&self, record: &vcf::Record, indices: &[(usize, String)]) -> bool {
+        (1..=record.alternate_bases().len()).any(|allele| !self.carriers(record, indices, allele).is_empty())
+    }
+
+    /// True if the record passes the selection: every record without sample
+    /// options, or else the records carried by a selected sample.
+    pub fn passes_record(&self, record: &vcf::Record, indices: &[(usize, String)]) -> bool {
+        !self.is_active() || self.is_carried(record, indices)
+    }
+
+    /// Same selection for variants read from MAF or tsv files, where each
+    /// line is a single sample.
+    pub fn passes_variant(&self, variant: &Variant) -> bool {
+        if let Some(samples) = &self.samples {
+            match &variant.sample {
+                Some(sample) if samples.contains(sample) => {},
+                _ => return false,
+            }
+        }
+        if self.min_depth > 0 {
+            match variant.depth {
+                Some(depth) if depth >= self.min_depth => {},
+                _ => return false,
+            }
+        }
+        if self.min_vaf > 0.0 {
+            match (variant.alt_depth, variant.depth) {
+                (Some(alt), Some(depth)) if depth > 0 && alt as f64 / depth as f64 >= self.min_vaf => {},
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    fn is_carrier(&self, sample: &Sample<'_>, allele: usize) -> bool {
+        let has_allele = match sample.genotype() {
+            Some(Ok(genotype)) => genotype.iter().any(|x| x.position() == Some(allele)),
+            _ => false,
+        };
+        if !has_allele {
+            return false;
+        }
+
+        let allele_depths = read_depths(sample);
+        let depth = match sample.get(&key::READ_DEPTH) {
+            Some(Some(Value::Integer(depth))) => Some((*depth).max(0) as u32),
+            _ => allele_depths.as_ref().map(|x| x.iter().sum()),
+        };
+
+        if self.min_depth > 0 {
+            match depth {
+                Some(depth) if depth >= self.min_depth => {},
+                _ => return false,
+            }
+        }
+
+        if self.min_vaf > 0.0 {
+            let vaf = allele_depths.and_then(|x| {
+                let total: u32 = x.iter().sum();
+                let alt = *x.get(allele)?;
+                if total == 0 {
+                    None
+                } else {
+                    Some(alt as f64 / total as f64)
+                }
+            });
+            match vaf {
+                Some(vaf) if vaf >= self.min_vaf => {},
+                _ => return false,
+            }
+        }
+
+        true
+    }
+}
+
+// allelic depths (AD) with missing values as 0
+fn read_depths(sample: &Sample<'_>) -> Option<Vec<u32>> {
+    match sample.get(&key::READ_DEPTHS) {
+        Some(Some(Value::Array(Array::Integer(values)))) => {
+            Some(values.iter().map(|x| x.unwrap_or(0).max(0) as u32).collect())
+        },
+        Some(Some(Value::Integer(value))) => Some(vec![(*value).max(0) as u32]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::vcf_records;
+
+    const NO_GENOTYPES: &str = "##fileformat=VCFv4.3\n\
+        ##contig=<ID=chr1,length=1000>\n\
+        ##FORMAT=<ID=DP,Number=1,Type=Integer,Description=\"Read depth\">\n\
+        #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tNORMAL\tTUMOR\n\
+        chr1\t100\t.\tA\tC\t.\tPASS\t.\tDP\t30\t40\n\
+        chr1\t200\t.\tG\tT\t.\tPASS\t.\tDP\t25\t35\n";
+
+    #[test]
+    fn records_without_genotypes_pass_without_sample_options() {
+        let (header, records) = vcf_records(NO_GENOTYPES);
+        let sample_filter = SampleFilter { samples: None, min_depth: 0, min_vaf: 0.0 };
+        let indices = sample_filter.sample_indices(&header);
+        for record in &records {
+            assert!(sample_filter.passes_record(record, &indices));
+            let mut record_out = record.clone();
+            sample_filter.subset_record(&mut record_out, &indices);
+            assert_eq!(record_out.to_string(), record.to_string());
+        }
+
+        // no sample carries them by GT once a filter is asked for
+        let sample_filter = SampleFilter { samples: None, min_depth: 10, min_vaf: 0.0 };
+        assert!(records.iter().all(|record| !sample_filter.passes_record(record, &indices)));
+    }
+}
@@ -0,0 +1,141 @@
+
+
+// small statistics helpers for the read evidence of the variants
+
+/// Mean of the values, None if empty.
+pub fn mean<T: Copy + Into<f64>>(values: &[T]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().map(|x| (*x).into()).sum::<f64>() / values.len() as f64)
+    }
+}
+
+/// Median of the values, None if empty.
+pub fn median(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let middle = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        Some((sorted[middle - 1] + sorted[middle]) / 2.0)
+    } else {
+        Some(sorted[middle])
+    }
+}
+
+/// Median absolute deviation from the median (not scaled), None if empty.
+pub fn median_absolute_deviation(values: &[f64]) -> Option<f64> {
+    let center = median(values)?;
+    let deviations: Vec<f64> = values.iter().map(|x| (x - center).abs()).collect();
+    median(&deviations)
+}
+
+// ln(k!) for k in 0..=n, so each factorial of a test is a lookup
+fn ln_factorials(n: u64) -> Vec<f64> {
+    let mut table = Vec::with_capacity(n as usize + 1);
+    let mut total = 0.0;
+    table.push(total);
+    for k in 1..=n {
+        total += (k as f64).ln();
+        table.push(total);
+    }
+    table
+}
+
+/// Two sided Fisher's exact test of the 2x2 table [[a, b], [c, d]].
+pub fn fisher_exact(a: u64, b: u64, c: u64, d: u64) -> f64 {
+    let row1 = a + b;
+    let row2 = c + d;
+    let col1 = a + c;
+    let n = row1 + row2;
+    if n == 0 {
+        return 1.0;
+    }
+
+    let ln_factorials = ln_factorials(n);
+    let ln_factorial = |k: u64| ln_factorials[k as usize];
+
+    // hypergeometric probability of each table with the same margins
+    let ln_margins = ln_factorial(row1) + ln_factorial(row2) + ln_factorial(col1) + ln_factorial(n - col1) - ln_factorial(n);
+    let probability = |x: u64| {
+        (ln_margins
+            - ln_factorial(x)
+            - ln_factorial(row1 - x)
+            - ln_factorial(col1 - x)
+            - ln_factorial(row2 + x - col1))
+            .exp()
+    };
+
+    let observed = probability(a);
+    let min_x = col1.saturating_sub(row2);
+    let max_x = row1.min(col1);
+    let p_value: f64 = (min_x..=max_x)
+        .map(probability)
+        .filter(|p| *p <= observed * (1.0 + 1e-7))
+        .sum();
+    p_value.min(1.0)
+}
+
+/// Two sample Kolmogorov-Smirnov statistic (largest distance between the
+/// empirical distributions), None if any sample is empty.
+pub fn ks_statistic(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+    let mut a = a.to_vec();
+    let mut b = b.to_vec();
+    a.sort_by(|x, y| x.total_cmp(y));
+    b.sort_by(|x, y| x.total_cmp(y));
+
+    let (mut i, mut j) = (0, 0);
+    let mut distance: f64 = 0.0;
+    while i < a.len() && j < b.len() {
+        // ties move both samples before comparing
+        let value = a[i].min(b[j]);
+        while i < a.len() && a[i] <= value {
+            i += 1;
+        }
+        while j < b.len() && b[j] <= value {
+            j += 1;
+        }
+        distance = distance.max((i as f64 / a.len() as f64 - j as f64 / b.len() as f64).abs());
+    }
+    Some(distance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fisher_exact_matches_reference_p_values() {
+        // table and two sided p-value (as scipy.stats.fisher_exact)
+        let cases = [
+            ((3, 1, 1, 3), 0.4857142857142857),
+            ((1, 9, 11, 3), 0.0027594561852200836),
+            ((10, 0, 0, 10), 1.082508822446903e-05),
+            ((0, 0, 0, 0), 1.0),
+        ];
+        for ((a, b, c, d), expected) in cases {
+            let p_value = fisher_exact(a, b, c, d);
+            assert!((p_value - expected).abs() <= expected * 1e-9, "{} {} {} {}: {}", a, b, c, d, p_value);
+        }
+    }
+
+    #[test]
+    fn ks_statistic_is_the_largest_distance() {
+        let cases: Vec<(Vec<f64>, Vec<f64>, Option<f64>)> = vec![
+            (vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0], Some(1.0)),
+            (vec![1.0, 2.0, 3.0, 4.0], vec![3.0, 4.0, 5.0, 6.0], Some(0.5)),
+            // ties move both samples
+            (vec![1.0, 1.0, 2.0], vec![1.0, 2.0, 2.0], Some(1.0 / 3.0)),
+            (vec![], vec![1.0], None),
+        ];
+        for (a, b, expected) in cases {
+            assert_eq!(ks_statistic(&a, &b), expected, "{:?} {:?}", a, b);
+        }
+    }
+}
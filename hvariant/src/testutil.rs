@@ -0,0 +1,28 @@
+// records parsed from text for the unit tests
+
+use noodles::sam;
+use noodles::vcf;
+
+/// Alignment record from a SAM line, aligned to chr1 or chr2 (1000 bases
+/// each).
+pub fn sam_record(line: &str) -> sam::alignment::Record {
+    let header: sam::Header = "@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr2\tLN:1000\n".parse().unwrap();
+    let line = format!("{}\n", line);
+    let mut reader = sam::Reader::new(line.as_bytes());
+    let mut records = reader.records(&header);
+    records.next().unwrap().unwrap()
+}
+
+/// Unpaired read in chr1 from the name, flags, position, cigar and sequence.
+pub fn read(name: &str, flags: u16, position: usize, cigar: &str, sequence: &str) -> sam::alignment::Record {
+    sam_record(&format!("{}\t{}\tchr1\t{}\t60\t{}\t*\t0\t0\t{}\t*", name, flags, position, cigar, sequence))
+}
+
+
+/// Header and records of a vcf file.
+pub fn vcf_records(text: &str) -> (vcf::Header, Vec<vcf::Record>) {
+    let mut reader = vcf::Reader::new(text.as_bytes());
+    let header = reader.read_header().unwrap();
+    let records = reader.records(&header).map(|result| result.unwrap()).collect();
+    (header, records)
+}
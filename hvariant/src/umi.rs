@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use noodles::sam;
+use noodles::sam::record::data::field::Tag;
+
+// unique molecular identifiers: the reads of the same molecule (a family,
+// PCR duplicates of each other) have the same UMI tag and the same fragment
+// start, their sequences are collapsed into a consensus
+
+/// UMI tag of the reads (i.e. RX or MI) and the minimum reads in a family
+/// to count it.
+pub struct UmiFamilies {
+    pub tag: Tag,
+    pub min_family_size: usize,
+}
+
+impl UmiFamilies {
+    /// Family of a read: the value of the tag and the start of the fragment
+    /// (the leftmost mate for pairs in the same contig). None without tag.
+    pub fn family(&self, record: &sam::alignment::Record) -> Option<String> {
+        let umi = record.data().get(&self.tag)?;
+        let start = record.alignment_start().map(usize::from)?;
+        let mate_start = match (record.reference_sequence_id(), record.mate_reference_sequence_id()) {
+            (Some(id), Some(mate_id)) if id == mate_id && record.flags().is_segmented() => {
+                record.mate_alignment_start().map(usize::from)
+            },
+            _ => None,
+        };
+        let fragment_start = mate_start.map_or(start, |mate_start| mate_start.min(start));
+        Some(format!("{}:{}", umi, fragment_start))
+    }
+}
+
+/// Consensus of the sequences of a family: the sequence of more than half
+/// of the reads, or base by base the base of more than half of them (N
+/// otherwise) if all have the same length, or else the most common one.
+pub fn consensus(sequences: &[&str]) -> String {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for sequence in sequences {
+        *counts.entry(sequence).or_insert(0) += 1;
+    }
+    // first in the family on ties, so the result doesn't depend on the hash
+    let (most_common, most_common_count) = sequences
+        .iter()
+        .map(|sequence| (*sequence, counts[sequence]))
+        .fold(("", 0), |best, x| if x.1 > best.1 { x } else { best });
+    if most_common_count * 2 > sequences.len() {
+        return most_common.to_string();
+    }
+
+    let rows: Vec<Vec<char>> = sequences.iter().map(|x| x.chars().collect()).collect();
+    let length = rows[0].len();
+    if rows.iter().any(|x| x.len() != length) {
+        return most_common.to_string();
+    }
+    (0..length)
+        .map(|idx| {
+            let mut base_counts: HashMap<char, usize> = HashMap::new();
+            for row in &rows {
+                *base_counts.entry(row[idx]).or_insert(0) += 1;
+            }
+            match base_counts.into_iter().find(|(_, count)| count * 2 > rows.len()) {
+                Some((base, _)) => base,
+                None if rows[0][idx].is_lowercase() => 'n',
+                None => 'N',
+            }
+        })
+        .collect()
+}
+
+/// Families of the reads and reads left out of them.
+#[derive(Default)]
+pub struct FamilyCounts {
+    pub families: u64,
+    /// families with fewer reads than the minimum
+    pub small_families: u64,
+    /// reads without the UMI tag
+    pub reads_without_umi: u64,
+}
+
+impl FamilyCounts {
+    pub fn merge(&mut self, other: FamilyCounts) {
+        self.families += other.families;
+        self.small_families += other.small_families;
+        self.reads_without_umi += other.reads_without_umi;
+    }
+
+    pub fn report(&self) {
+        eprintln!("UMI families counted: {}", self.families);
+        eprintln!("UMI families below the minimum size: {}", self.small_families);
+        eprintln!("Reads without UMI: {}", self.reads_without_umi);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::sam_record;
+
+    #[test]
+    fn family_is_the_umi_and_the_fragment_start() {
+        let families = UmiFamilies { tag: "RX".parse().unwrap(), min_family_size: 1 };
+        let cases = [
+            ("r1\t99\tchr1\t100\t60\t4M\t=\t300\t0\tACGT\t*\tRX:Z:AACT", Some("AACT:100")),
+            // the mate has the same family
+            ("r1\t147\tchr1\t300\t60\t4M\t=\t100\t0\tACGT\t*\tRX:Z:AACT", Some("AACT:100")),
+            // the mate in another contig, the read start
+            ("r1\t97\tchr1\t300\t60\t4M\tchr2\t100\t0\tACGT\t*\tRX:Z:AACT", Some("AACT:300")),
+            ("r1\t99\tchr1\t100\t60\t4M\t=\t300\t0\tACGT\t*\tMI:Z:7", None),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(families.family(&sam_record(line)).as_deref(), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn consensus_of_the_family() {
+        let cases: Vec<(Vec<&str>, &str)> = vec![
+            // the sequence of more than half of the reads
+            (vec!["ACGT", "ACTT", "ACGT"], "ACGT"),
+            // base by base, N without majority
+            (vec!["ACGT", "ACTT", "AGTT", "ACGA"], "ACNT"),
+            (vec!["acgt", "actt"], "acnt"),
+            // different lengths, the first of the most common
+            (vec!["ACGT", "ACG", "ACGTT"], "ACGT"),
+        ];
+        for (sequences, expected) in cases {
+            assert_eq!(consensus(&sequences), expected, "{:?}", sequences);
+        }
+    }
+}
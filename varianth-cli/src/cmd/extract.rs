@@ -0,0 +1,318 @@
+
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use log::info;
+
+// internal dependencies
+use varianth_core::catalogue::{cosine_similarity, Catalogue};
+
+// small constant to avoid divisions by 0 in the multiplicative updates
+const EPS: f64 = 1e-12;
+
+/*
+STRUCTS
+*/
+
+// V (mutation types x samples) ~ W (mutation types x rank) * H (rank x samples)
+struct Factorisation {
+    w: Vec<Vec<f64>>,
+    h: Vec<Vec<f64>>,
+    error: f64,
+}
+
+struct RankSummary {
+    rank: usize,
+    // None with a single restart, there is nothing to compare with
+    mean_stability: Option<f64>,
+    min_stability: Option<f64>,
+    error: f64,
+    mean_cosine: f64,
+}
+
+/*
+RUNS
+*/
+
+#[allow(clippy::too_many_arguments)]
+pub fn run (
+    catalogue_path: PathBuf,
+    min_rank: usize,
+    max_rank: usize,
+    restarts: usize,
+    iterations: usize,
+    seed: u64,
+    prefix: String,
+    verbose: bool
+) -> Result<(), std::io::Error> {
+
+    // ranks and restarts of at least 1 are checked by the arguments parser
+    if min_rank > max_rank {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("The minimum rank ({}) is above the maximum rank ({})", min_rank, max_rank),
+        ));
+    }
+
+    let catalogue = Catalogue::read_tsv(BufReader::new(File::open(catalogue_path)?))?;
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut summaries: Vec<RankSummary> = Vec::new();
+
+    for rank in min_rank..=max_rank {
+        if verbose {
+            info!("Running rank {} with {} restarts", rank, restarts);
+        }
+
+        let mut solutions: Vec<Factorisation> = (0..restarts)
+            .map(|_| factorise(&catalogue.counts, rank, iterations, &mut rng))
+            .collect();
+        solutions.sort_by(|a, b| a.error.total_cmp(&b.error));
+
+        // stability of each signature of the best solution across restarts
+        let best = &solutions[0];
+        let mut matched: Vec<Vec<f64>> = vec![Vec::new(); rank];
+        for other in solutions.iter().skip(1) {
+            for (k, similarity) in match_signatures(&best.w, &other.w).into_iter().enumerate() {
+                matched[k].push(similarity);
+            }
+        }
+        let stabilities: Vec<f64> = matched
+            .iter()
+            .map(|x| x.iter().sum::<f64>() / x.len().max(1) as f64)
+            .collect();
+        let has_stability = solutions.len() > 1;
+
+        let reconstruction = multiply(&best.w, &best.h);
+        let n_samples = catalogue.samples.len();
+        let mean_cosine = (0..n_samples)
+            .map(|j| {
+                let observed = catalogue.column(j);
+                let predicted: Vec<f64> = reconstruction.iter().map(|row| row[j]).collect();
+                cosine_similarity(&predicted, &observed)
+            })
+            .sum::<f64>() / n_samples.max(1) as f64;
+
+        let summary = RankSummary {
+            rank,
+            mean_stability: has_stability.then(|| stabilities.iter().sum::<f64>() / rank as f64),
+            min_stability: has_stability.then(|| stabilities.iter().cloned().fold(f64::INFINITY, f64::min)),
+            error: best.error,
+            mean_cosine,
+        };
+        if verbose {
+            info!("Rank {}: stability {}, error {:.3}", rank, format_stability(summary.mean_stability), summary.error);
+        }
+
+        write_solution(&catalogue, best, &prefix, rank)?;
+        summaries.push(summary);
+    }
+
+    let mut out = BufWriter::new(File::create(format!("{}.ranks.tsv", prefix))?);
+    writeln!(out, "Rank\tMeanStability\tMinStability\tReconstructionError\tMeanCosineSimilarity")?;
+    for summary in &summaries {
+        writeln!(out, "{}\t{}\t{}\t{:.6}\t{:.6}",
+            summary.rank,
+            format_stability(summary.mean_stability),
+            format_stability(summary.min_stability),
+            summary.error,
+            summary.mean_cosine)?;
+    }
+
+    Ok(())
+}
+
+// NA without restarts to compare
+fn format_stability(stability: Option<f64>) -> String {
+    match stability {
+        Some(stability) => format!("{:.6}", stability),
+        None => String::from("NA"),
+    }
+}
+
+fn signature_names(rank: usize) -> Vec<String> {
+    (1..=rank).map(|k| format!("Signature{}", k)).collect()
+}
+
+fn write_solution(catalogue: &Catalogue, solution: &Factorisation, prefix: &str, rank: usize) -> Result<(), std::io::Error> {
+    let names = signature_names(rank);
+
+    let mut signatures = Catalogue::new(catalogue.mutation_types.clone(), names.clone());
+    signatures.counts = solution.w.clone();
+    let mut out = BufWriter::new(File::create(format!("{}.k{}.signatures.tsv", prefix, rank))?);
+    signatures.write_tsv(&mut out)?;
+
+    let mut out = BufWriter::new(File::create(format!("{}.k{}.exposures.tsv", prefix, rank))?);
+    write!(out, "Sample")?;
+    for name in &names {
+        write!(out, "\t{}", name)?;
+    }
+    writeln!(out)?;
+    for (j, sample) in catalogue.samples.iter().enumerate() {
+        write!(out, "{}", sample)?;
+        for row in &solution.h {
+            write!(out, "\t{:.6}", row[j])?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/*
+NMF
+*/
+
+fn multiply(a: &[Vec<f64>], b: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let n = b.first().map_or(0, |x| x.len());
+    a.iter()
+        .map(|row| {
+            (0..n)
+                .map(|j| row.iter().zip(b).map(|(x, b_row)| x * b_row[j]).sum())
+                .collect()
+        })
+        .collect()
+}
+
+fn transpose(a: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let n = a.first().map_or(0, |x| x.len());
+    (0..n).map(|j| a.iter().map(|row| row[j]).collect()).collect()
+}
+
+// frobenius norm of V - WH
+fn frobenius_error(v: &[Vec<f64>], w: &[Vec<f64>], h: &[Vec<f64>]) -> f64 {
+    let wh = multiply(w, h);
+    v.iter()
+        .zip(&wh)
+        .flat_map(|(a, b)| a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)))
+        .sum::<f64>()
+        .sqrt()
+}
+
+// multiplicative updates (Lee and Seung) from a random start, the
+// signatures are scaled to sum 1 and the exposures to keep WH unchanged
+fn factorise(v: &[Vec<f64>], rank: usize, iterations: usize, rng: &mut StdRng) -> Factorisation {
+    let m = v.len();
+    let n = v.first().map_or(0, |x| x.len());
+    let mean = v.iter().flatten().sum::<f64>() / (m * n).max(1) as f64;
+    let scale = (mean / rank as f64).sqrt().max(EPS);
+
+    let mut w: Vec<Vec<f64>> = (0..m).map(|_| (0..rank).map(|_| rng.gen::<f64>() * scale).collect()).collect();
+    let mut h: Vec<Vec<f64>> = (0..rank).map(|_| (0..n).map(|_| rng.gen::<f64>() * scale).collect()).collect();
+
+    for _ in 0..iterations {
+        // H <- H * (Wt V) / (Wt W H)
+        let wt = transpose(&w);
+        let numerator = multiply(&wt, v);
+        let denominator = multiply(&multiply(&wt, &w), &h);
+        for k in 0..rank {
+            for j in 0..n {
+                h[k][j] *= numerator[k][j] / (denominator[k][j] + EPS);
+            }
+        }
+
+        // W <- W * (V Ht) / (W H Ht)
+        let ht = transpose(&h);
+        let numerator = multiply(v, &ht);
+        let denominator = multiply(&w, &multiply(&h, &ht));
+        for i in 0..m {
+            for k in 0..rank {
+                w[i][k] *= numerator[i][k] / (denominator[i][k] + EPS);
+            }
+        }
+    }
+
+    for k in 0..rank {
+        let total: f64 = w.iter().map(|row| row[k]).sum();
+        if total > EPS {
+            for row in w.iter_mut() {
+                row[k] /= total;
+            }
+            for value in h[k].iter_mut() {
+                *value *= total;
+            }
+        }
+    }
+
+    let error = frobenius_error(v, &w, &h);
+    Factorisation { w, h, error }
+}
+
+// greedy one to one matching of the signatures (columns) of `other` to the
+// ones in `reference`, returns the cosine similarity of each match
+fn match_signatures(reference: &[Vec<f64>], other: &[Vec<f64>]) -> Vec<f64> {
+    let reference_t = transpose(reference);
+    let other_t = transpose(other);
+    let rank = reference_t.len();
+
+    let mut pairs: Vec<(usize, usize, f64)> = Vec::with_capacity(rank * rank);
+    for (a, sig_a) in reference_t.iter().enumerate() {
+        for (b, sig_b) in other_t.iter().enumerate() {
+            pairs.push((a, b, cosine_similarity(sig_a, sig_b)));
+        }
+    }
+    pairs.sort_by(|x, y| y.2.total_cmp(&x.2));
+
+    let mut similarities = vec![0.0; rank];
+    let mut used_a = vec![false; rank];
+    let mut used_b = vec![false; rank];
+    for (a, b, similarity) in pairs {
+        if used_a[a] || used_b[b] {
+            continue;
+        }
+        used_a[a] = true;
+        used_b[b] = true;
+        similarities[a] = similarity;
+    }
+    similarities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_signatures_pairs_each_signature_once() {
+        let reference = vec![vec![1.0, 0.0, 0.0], vec![0.0, 1.0, 0.0], vec![0.0, 0.0, 1.0]];
+        // reference (other) columns, expected similarity of each reference column
+        let cases: Vec<(Vec<Vec<f64>>, Vec<f64>)> = vec![
+            // same signatures
+            (reference.clone(), vec![1.0, 1.0, 1.0]),
+            // permuted and scaled
+            (vec![vec![0.0, 2.0, 0.0], vec![0.0, 0.0, 3.0], vec![1.0, 0.0, 0.0]], vec![1.0, 1.0, 1.0]),
+            // two copies of the first signature and a mix of the others:
+            // the mix goes to the second one, the third gets the copy
+            (vec![vec![1.0, 1.0, 0.0], vec![0.0, 0.0, 1.0], vec![0.0, 0.0, 1.0]], vec![1.0, 1.0 / 2f64.sqrt(), 0.0]),
+        ];
+        for (other, expected) in cases {
+            let similarities = match_signatures(&reference, &other);
+            for (similarity, expected) in similarities.iter().zip(&expected) {
+                assert!((similarity - expected).abs() < 1e-9, "{:?} != {:?}", similarities, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn factorise_recovers_an_exact_rank() {
+        // two signatures mixed in four samples
+        let w = vec![vec![0.6, 0.0], vec![0.3, 0.1], vec![0.1, 0.3], vec![0.0, 0.6]];
+        let h = vec![vec![100.0, 10.0, 50.0, 0.0], vec![0.0, 80.0, 50.0, 120.0]];
+        let v = multiply(&w, &h);
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let solution = factorise(&v, 2, 5000, &mut rng);
+        let total: f64 = v.iter().flatten().sum();
+        assert!(solution.error / total < 1e-2, "error {}", solution.error);
+        for k in 0..2 {
+            let column_total: f64 = solution.w.iter().map(|row| row[k]).sum();
+            assert!((column_total - 1.0).abs() < 1e-9);
+        }
+        for similarity in match_signatures(&w, &solution.w) {
+            assert!(similarity > 0.99, "similarity {}", similarity);
+        }
+    }
+}
@@ -0,0 +1,361 @@
+
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use log::{info, warn};
+
+// internal dependencies
+use varianth_core::catalogue::{cosine_similarity, Catalogue};
+
+/*
+STRUCTS
+*/
+
+struct SampleFit {
+    mutations: f64,
+    exposures: Vec<f64>,
+    cosine: f64,
+    // bootstrap confidence intervals, one (lower, upper) per signature
+    intervals: Option<Vec<(f64, f64)>>,
+}
+
+/*
+RUNS
+*/
+
+#[allow(clippy::too_many_arguments)]
+pub fn run (
+    catalogue_path: PathBuf,
+    signatures_path: PathBuf,
+    output: PathBuf,
+    min_exposure: f64,
+    bootstrap: usize,
+    ci: f64,
+    seed: u64,
+    verbose: bool
+) -> Result<(), std::io::Error> {
+
+    let catalogue = Catalogue::read_tsv(BufReader::new(File::open(catalogue_path)?))?;
+    let signatures = Catalogue::read_tsv(BufReader::new(File::open(signatures_path)?))?;
+    let signature_matrix = match_signatures(&catalogue, &signatures)?;
+
+    if verbose {
+        info!("Fitting {} signatures to {} samples", signatures.samples.len(), catalogue.samples.len());
+    }
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut fits: Vec<SampleFit> = Vec::with_capacity(catalogue.samples.len());
+    for (j, sample) in catalogue.samples.iter().enumerate() {
+        let observed = catalogue.column(j);
+        let mutations: f64 = observed.iter().sum();
+        let exposures = fit_sparse(&signature_matrix, &observed, min_exposure);
+        let cosine = cosine_similarity(&reconstruct(&signature_matrix, &exposures), &observed);
+
+        let intervals = if bootstrap > 0 {
+            Some(bootstrap_intervals(&signature_matrix, &observed, min_exposure, bootstrap, ci, &mut rng))
+        } else {
+            None
+        };
+
+        if verbose {
+            info!("Sample {} fitted, cosine similarity {:.3}", sample, cosine);
+        }
+        if mutations == 0.0 {
+            warn!("Sample {} has no mutations", sample);
+        }
+
+        fits.push(SampleFit { mutations, exposures, cosine, intervals });
+    }
+
+    let mut out = BufWriter::new(File::create(output)?);
+    write!(out, "Sample\tMutations\tCosineSimilarity")?;
+    for signature in &signatures.samples {
+        write!(out, "\t{}", signature)?;
+    }
+    if bootstrap > 0 {
+        for signature in &signatures.samples {
+            write!(out, "\t{}_lower\t{}_upper", signature, signature)?;
+        }
+    }
+    writeln!(out)?;
+
+    for (sample, fit) in catalogue.samples.iter().zip(&fits) {
+        write!(out, "{}\t{}\t{:.6}", sample, fit.mutations, fit.cosine)?;
+        for exposure in &fit.exposures {
+            write!(out, "\t{:.6}", exposure)?;
+        }
+        if let Some(intervals) = &fit.intervals {
+            for (lower, upper) in intervals {
+                write!(out, "\t{:.6}\t{:.6}", lower, upper)?;
+            }
+        }
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+// reorders the signatures to the rows of the catalogue, returns a matrix
+// with one row per mutation type and one column per signature
+pub fn match_signatures(catalogue: &Catalogue, signatures: &Catalogue) -> Result<Vec<Vec<f64>>, std::io::Error> {
+    catalogue.mutation_types.iter().map(|mutation_type| {
+        match signatures.mutation_type_index(mutation_type) {
+            Some(i) => Ok(signatures.counts[i].clone()),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Mutation type {} is not in the signatures file", mutation_type),
+            )),
+        }
+    }).collect()
+}
+
+// drops the signatures that contribute less than min_exposure (fraction of
+// the total) and refits with the rest until no more are removed
+fn fit_sparse(signatures: &[Vec<f64>], observed: &[f64], min_exposure: f64) -> Vec<f64> {
+    let n_signatures = signatures.first().map_or(0, |x| x.len());
+    let mut active: Vec<bool> = vec![true; n_signatures];
+
+    loop {
+        let exposures = nnls(signatures, observed, &active);
+        let total: f64 = exposures.iter().sum();
+        if total == 0.0 || min_exposure <= 0.0 {
+            return exposures;
+        }
+
+        let mut removed = false;
+        for (k, exposure) in exposures.iter().enumerate() {
+            if active[k] && exposure / total < min_exposure {
+                active[k] = false;
+                removed = true;
+            }
+        }
+        if !removed {
+            return exposures;
+        }
+    }
+}
+
+fn bootstrap_intervals(
+    signatures: &[Vec<f64>],
+    observed: &[f64],
+    min_exposure: f64,
+    replicates: usize,
+    ci: f64,
+    rng: &mut StdRng,
+) -> Vec<(f64, f64)> {
+    let n_signatures = signatures.first().map_or(0, |x| x.len());
+    let mut samples: Vec<Vec<f64>> = vec![Vec::with_capacity(replicates); n_signatures];
+
+    for _ in 0..replicates {
+        let resampled = resample(observed, rng);
+        let exposures = fit_sparse(signatures, &resampled, min_exposure);
+        for (k, exposure) in exposures.into_iter().enumerate() {
+            samples[k].push(exposure);
+        }
+    }
+
+    let alpha = (1.0 - ci) / 2.0;
+    samples.into_iter().map(|mut values| {
+        values.sort_by(|a, b| a.total_cmp(b));
+        (quantile(&values, alpha), quantile(&values, 1.0 - alpha))
+    }).collect()
+}
+
+// multinomial resampling of the mutations of one sample
+fn resample(observed: &[f64], rng: &mut StdRng) -> Vec<f64> {
+    let total: f64 = observed.iter().sum();
+    let mut cumulative = Vec::with_capacity(observed.len());
+    let mut acc = 0.0;
+    for value in observed {
+        acc += value;
+        cumulative.push(acc);
+    }
+
+    let mut out = vec![0.0; observed.len()];
+    if total <= 0.0 {
+        return out;
+    }
+    for _ in 0..(total.round() as usize) {
+        let draw = rng.gen::<f64>() * total;
+        let idx = cumulative.partition_point(|x| *x <= draw).min(observed.len() - 1);
+        out[idx] += 1.0;
+    }
+    out
+}
+
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let idx = (q * (sorted.len() - 1) as f64).round() as usize;
+    sorted[idx.min(sorted.len() - 1)]
+}
+
+pub fn reconstruct(signatures: &[Vec<f64>], exposures: &[f64]) -> Vec<f64> {
+    signatures
+        .iter()
+        .map(|row| row.iter().zip(exposures).map(|(s, e)| s * e).sum())
+        .collect()
+}
+
+/*
+NNLS
+*/
+
+// Lawson-Hanson active set algorithm on the normal equations, columns
+// that are not active are kept at 0.
+fn nnls(a: &[Vec<f64>], b: &[f64], active: &[bool]) -> Vec<f64> {
+    let n = active.len();
+    let tol = 1e-10;
+
+    let mut ata = vec![vec![0.0; n]; n];
+    let mut atb = vec![0.0; n];
+    for (row, value) in a.iter().zip(b) {
+        for i in 0..n {
+            atb[i] += row[i] * value;
+            for j in 0..n {
+                ata[i][j] += row[i] * row[j];
+            }
+        }
+    }
+
+    let gradient = |x: &[f64]| -> Vec<f64> {
+        (0..n)
+            .map(|i| atb[i] - (0..n).map(|j| ata[i][j] * x[j]).sum::<f64>())
+            .collect()
+    };
+
+    let mut x = vec![0.0; n];
+    let mut passive = vec![false; n];
+
+    for _ in 0..(3 * n.max(1)) {
+        let w = gradient(&x);
+        let candidate = (0..n)
+            .filter(|&j| active[j] && !passive[j] && w[j] > tol)
+            .max_by(|&i, &j| w[i].total_cmp(&w[j]));
+        let t = match candidate {
+            Some(t) => t,
+            None => break,
+        };
+        passive[t] = true;
+
+        loop {
+            let z = solve_passive(&ata, &atb, &passive);
+            if (0..n).all(|j| !passive[j] || z[j] > tol) {
+                x = z;
+                break;
+            }
+
+            let alpha = (0..n)
+                .filter(|&j| passive[j] && z[j] <= tol)
+                .map(|j| if x[j] > z[j] { x[j] / (x[j] - z[j]) } else { 0.0 })
+                .fold(f64::INFINITY, f64::min);
+            for j in 0..n {
+                x[j] += alpha * (z[j] - x[j]);
+                if passive[j] && x[j] <= tol {
+                    passive[j] = false;
+                    x[j] = 0.0;
+                }
+            }
+        }
+    }
+    x
+}
+
+// solves the unconstrained least squares restricted to the passive set
+fn solve_passive(ata: &[Vec<f64>], atb: &[f64], passive: &[bool]) -> Vec<f64> {
+    let idx: Vec<usize> = (0..passive.len()).filter(|&j| passive[j]).collect();
+    let m = idx.len();
+
+    let mut system: Vec<Vec<f64>> = idx
+        .iter()
+        .map(|&i| {
+            let mut row: Vec<f64> = idx.iter().map(|&j| ata[i][j]).collect();
+            row.push(atb[i]);
+            row
+        })
+        .collect();
+
+    // gaussian elimination with partial pivoting
+    for col in 0..m {
+        let pivot = (col..m)
+            .max_by(|&i, &j| system[i][col].abs().total_cmp(&system[j][col].abs()))
+            .unwrap_or(col);
+        system.swap(col, pivot);
+        let diag = system[col][col];
+        if diag.abs() < 1e-12 {
+            continue;
+        }
+        let pivot_row = system[col].clone();
+        for row in system.iter_mut().skip(col + 1) {
+            let factor = row[col] / diag;
+            for (value, pivot_value) in row.iter_mut().zip(&pivot_row).skip(col) {
+                *value -= factor * pivot_value;
+            }
+        }
+    }
+
+    let mut solution = vec![0.0; m];
+    for row in (0..m).rev() {
+        let diag = system[row][row];
+        if diag.abs() < 1e-12 {
+            continue;
+        }
+        let rest: f64 = ((row + 1)..m).map(|k| system[row][k] * solution[k]).sum();
+        solution[row] = (system[row][m] - rest) / diag;
+    }
+
+    let mut out = vec![0.0; passive.len()];
+    for (pos, &j) in idx.iter().enumerate() {
+        out[j] = solution[pos];
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // signatures (mutation types by signatures), observed spectrum, minimum
+    // exposure and expected exposures
+    type Case = (Vec<Vec<f64>>, Vec<f64>, f64, Vec<f64>);
+
+    fn overlapping() -> Vec<Vec<f64>> {
+        vec![vec![0.5, 0.0], vec![0.3, 0.2], vec![0.2, 0.3], vec![0.0, 0.5]]
+    }
+
+    fn orthogonal() -> Vec<Vec<f64>> {
+        vec![vec![0.5, 0.0], vec![0.5, 0.0], vec![0.0, 0.5], vec![0.0, 0.5]]
+    }
+
+    #[test]
+    fn fit_sparse_recovers_known_mixtures() {
+        let cases: Vec<Case> = vec![
+            // 3 * s1 + 5 * s2
+            (overlapping(), vec![1.5, 1.9, 2.1, 2.5], 0.0, vec![3.0, 5.0]),
+            // 10 * s1 + 0.1 * s2, s2 is kept without minimum
+            (orthogonal(), vec![5.0, 5.0, 0.05, 0.05], 0.0, vec![10.0, 0.1]),
+            // s2 is below 5% of the total, removed and refitted
+            (orthogonal(), vec![5.0, 5.0, 0.05, 0.05], 0.05, vec![10.0, 0.0]),
+        ];
+        for (signatures, observed, min_exposure, expected) in cases {
+            let exposures = fit_sparse(&signatures, &observed, min_exposure);
+            assert_eq!(exposures.len(), expected.len());
+            for (exposure, expected) in exposures.iter().zip(&expected) {
+                assert!((exposure - expected).abs() < 1e-6, "{:?} != {:?} for {:?}", exposures, expected, observed);
+            }
+        }
+    }
+
+    #[test]
+    fn nnls_keeps_inactive_signatures_at_zero() {
+        let exposures = nnls(&overlapping(), &[1.5, 1.9, 2.1, 2.5], &[true, false]);
+        assert_eq!(exposures[1], 0.0);
+        assert!(exposures[0] > 0.0);
+    }
+}
@@ -27,14 +27,14 @@ STRUCTS
 */
 
 #[derive(Serialize, Deserialize)]
-struct TotalCount {
-    seqnames: Vec<String>,
-    counts: FxHashMap<String, FxHashMap<String, usize>>,
+pub(crate) struct TotalCount {
+    pub(crate) seqnames: Vec<String>,
+    pub(crate) counts: FxHashMap<String, FxHashMap<String, usize>>,
 }
 
 #[derive(Serialize, Deserialize)]
-struct AggregatedCount {
-    counts: FxHashMap<String, usize>,
+pub(crate) struct AggregatedCount {
+    pub(crate) counts: FxHashMap<String, usize>,
 }
 
 /*
@@ -66,8 +66,7 @@ pub fn run (
     let run_result  = match (regions_str, regions_path) {
         (Some(rst), None) => {
             let regions = region_string_to_vec(&rst).expect("Error parsing the regions");
-            let res = run_indexed(fasta_path, regions, size, output, tbsize, verbose);
-            res
+            run_indexed(fasta_path, regions, size, output, tbsize, verbose)
         },
         (None, Some(_)) => {
             panic!("Not implemented");
@@ -77,8 +76,7 @@ pub fn run (
             std::process::exit(1);
         },
         (None, None) => {
-            let res = run_unindexed(fasta_path, size, output, tbsize, verbose);
-            res
+            run_unindexed(fasta_path, size, output, tbsize, verbose)
         }
     };
 
@@ -152,7 +150,7 @@ fn run_indexed(fasta_path: PathBuf, regions: Vec<Region>, size: usize, output: O
         agg_count.counts.insert(kmer_string, *count);
     }
 
-    let _ = serialize_to_json(output, &agg_count);
+    serialize_to_json(output, &agg_count);
 
     Ok(())
 }    
@@ -165,7 +163,7 @@ fn run_unindexed (fasta_path: PathBuf, size: usize, output: Option<PathBuf>, tab
         counts: FxHashMap::with_capacity_and_hasher(table_size, Default::default()),
     };
 
-    let mut fa = Builder::default()
+    let mut fa = Builder
         .build_from_path(fasta_path)?;
 
     loop {
@@ -204,11 +202,12 @@ fn run_unindexed (fasta_path: PathBuf, size: usize, output: Option<PathBuf>, tab
         info!("Seqnames: {:?}", total_count.seqnames);
     }
 
-    let _ = serialize_to_json(output, &total_count);
+    serialize_to_json(output, &total_count);
 
     Ok(())
 }
 
+#[allow(clippy::erasing_op, clippy::identity_op, clippy::assign_op_pattern, clippy::explicit_counter_loop)]
 fn slice_to_index(kmer: &[u8]) -> usize {
     let mut hash_val = 0;
     let mut count = 0;
@@ -245,7 +244,7 @@ fn index_to_string(idx: usize, ksize: usize) -> String {
         // starts with max_pos
         let intdiv = idx / 4_usize.pow(i as u32);
         // this is the remainder
-        idx = idx % 4_usize.pow(i as u32);
+        idx %= 4_usize.pow(i as u32);
 
         let base = match intdiv {
             0 => 'A',
@@ -260,7 +259,7 @@ fn index_to_string(idx: usize, ksize: usize) -> String {
     kmer_string
 }
 
-fn update_table(table: &mut Vec<usize>, sequence_buf: &Vec<u8>, ksize: usize) -> () {
+fn update_table(table: &mut [usize], sequence_buf: &[u8], ksize: usize) {
     let mut cursor = 0;
     let mut cend = ksize;
     while cend <= sequence_buf.len() {
@@ -272,7 +271,7 @@ fn update_table(table: &mut Vec<usize>, sequence_buf: &Vec<u8>, ksize: usize) ->
     }
 }
 
-fn serialize_to_json<T: Serialize>(json_path: Option<PathBuf>, obj: &T) -> () {
+fn serialize_to_json<T: Serialize>(json_path: Option<PathBuf>, obj: &T) {
     let total_count_json = serde_json::to_string(obj);
 
     let json_str = match total_count_json {
@@ -1,3 +1,6 @@
 
 pub mod ms;
 pub mod kmercount;
+pub mod fit;
+pub mod extract;
+pub mod normalize;
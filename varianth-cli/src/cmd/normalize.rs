@@ -0,0 +1,214 @@
+
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+
+// this is for fast hashing
+use rustc_hash::FxHashMap;
+
+use log::{info, warn};
+
+// internal dependencies
+use varianth_core::catalogue::{mutation_type_context, Catalogue};
+use varianth_core::sequence::reverse_complement;
+use super::kmercount::{AggregatedCount, TotalCount};
+
+/*
+RUNS
+*/
+
+pub fn run (
+    catalogue_path: PathBuf,
+    kcount_path: PathBuf,
+    target_path: Option<PathBuf>,
+    output: PathBuf,
+    verbose: bool
+) -> Result<(), std::io::Error> {
+
+    let catalogue = Catalogue::read_tsv(BufReader::new(File::open(catalogue_path)?))?;
+    let source = opportunities(&catalogue, &read_kmer_counts(kcount_path)?)?;
+
+    let normalized = match target_path {
+        Some(target_path) => {
+            if verbose {
+                info!("Renormalising the spectra to the target territory");
+            }
+            let target = opportunities(&catalogue, &read_kmer_counts(target_path)?)?;
+            renormalize(&catalogue, &source, &target)
+        },
+        None => {
+            if verbose {
+                info!("Computing the mutation rates per context");
+            }
+            rates(&catalogue, &source)
+        },
+    };
+
+    let mut out = BufWriter::new(File::create(output)?);
+    normalized.write_tsv(&mut out)?;
+
+    Ok(())
+}
+
+// kcount writes the counts of each contig (TotalCount) or of the whole set
+// of regions (AggregatedCount), both are collapsed here to a single table
+fn read_kmer_counts(path: PathBuf) -> Result<FxHashMap<String, usize>, std::io::Error> {
+    let json_str = std::fs::read_to_string(path)?;
+
+    if let Ok(total_count) = serde_json::from_str::<TotalCount>(&json_str) {
+        let mut counts: FxHashMap<String, usize> = FxHashMap::default();
+        for contig_counts in total_count.counts.values() {
+            for (kmer, count) in contig_counts {
+                *counts.entry(kmer.clone()).or_insert(0) += count;
+            }
+        }
+        return Ok(counts);
+    }
+
+    let agg_count: AggregatedCount = serde_json::from_str(&json_str)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    Ok(agg_count.counts)
+}
+
+// number of sites of each mutation type context, the context is counted
+// in both strands as the catalogue collapses them
+fn opportunities(catalogue: &Catalogue, kmer_counts: &FxHashMap<String, usize>) -> Result<Vec<f64>, std::io::Error> {
+    catalogue.mutation_types.iter().map(|mutation_type| {
+        let context = match mutation_type_context(mutation_type) {
+            Some(context) => context,
+            None => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Mutation type {} is not a substitution type", mutation_type),
+                ));
+            }
+        };
+        let context_rc = String::from_utf8_lossy(&reverse_complement(context.as_bytes())).to_string();
+
+        match (kmer_counts.get(&context), kmer_counts.get(&context_rc)) {
+            (Some(forward), Some(reverse)) => Ok((forward + reverse) as f64),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Context {} is not in the kcount table, was it run with -K {}?", context, context.len()),
+            )),
+        }
+    }).collect()
+}
+
+fn rates(catalogue: &Catalogue, opportunities: &[f64]) -> Catalogue {
+    let mut out = Catalogue::new(catalogue.mutation_types.clone(), catalogue.samples.clone());
+    for (i, row) in catalogue.counts.iter().enumerate() {
+        if opportunities[i] == 0.0 {
+            warn!("Context of {} has no sites, the rate is set to 0", catalogue.mutation_types[i]);
+            continue;
+        }
+        for (j, value) in row.iter().enumerate() {
+            out.counts[i][j] = value / opportunities[i];
+        }
+    }
+    out
+}
+
+// scales each context by the ratio of opportunities between territories
+// and keeps the total number of mutations of each sample
+fn renormalize(catalogue: &Catalogue, source: &[f64], target: &[f64]) -> Catalogue {
+    let source_total: f64 = source.iter().sum();
+    let target_total: f64 = target.iter().sum();
+
+    let mut out = Catalogue::new(catalogue.mutation_types.clone(), catalogue.samples.clone());
+    for (i, row) in catalogue.counts.iter().enumerate() {
+        if source[i] == 0.0 {
+            continue;
+        }
+        let ratio = (target[i] / target_total) / (source[i] / source_total);
+        for (j, value) in row.iter().enumerate() {
+            out.counts[i][j] = value * ratio;
+        }
+    }
+
+    for j in 0..catalogue.samples.len() {
+        let observed: f64 = catalogue.counts.iter().map(|row| row[j]).sum();
+        let scaled: f64 = out.counts.iter().map(|row| row[j]).sum();
+        if scaled > 0.0 {
+            for row in out.counts.iter_mut() {
+                row[j] *= observed / scaled;
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // kmer counts and expected opportunities (None for an error)
+    type OpportunitiesCase<'a> = (Vec<(&'a str, usize)>, Option<Vec<f64>>);
+    // counts, source and target opportunities and expected counts
+    type RenormalizeCase = (Vec<Vec<f64>>, Vec<f64>, Vec<f64>, Vec<Vec<f64>>);
+
+    fn catalogue(counts: Vec<Vec<f64>>) -> Catalogue {
+        let mut catalogue = Catalogue::new(
+            vec![String::from("A[C>A]A"), String::from("A[T>G]C")],
+            vec![String::from("S1"), String::from("S2")],
+        );
+        catalogue.counts = counts;
+        catalogue
+    }
+
+    fn kmer_counts(counts: &[(&str, usize)]) -> FxHashMap<String, usize> {
+        counts.iter().map(|(kmer, count)| (kmer.to_string(), *count)).collect()
+    }
+
+    #[test]
+    fn opportunities_count_both_strands() {
+        let cases: Vec<OpportunitiesCase> = vec![
+            // ACA and its reverse complement TGT, ATC and GAT
+            (vec![("ACA", 10), ("TGT", 5), ("ATC", 2), ("GAT", 3)], Some(vec![15.0, 5.0])),
+            // GAT is missing
+            (vec![("ACA", 10), ("TGT", 5), ("ATC", 2)], None),
+            // counted with another -K
+            (vec![("CA", 10), ("TG", 5), ("TC", 2), ("GA", 3)], None),
+        ];
+        let catalogue = catalogue(vec![vec![0.0, 0.0], vec![0.0, 0.0]]);
+        for (counts, expected) in cases {
+            let result = opportunities(&catalogue, &kmer_counts(&counts));
+            assert_eq!(result.ok(), expected, "{:?}", counts);
+        }
+    }
+
+    #[test]
+    fn opportunities_need_substitution_types() {
+        let mut catalogue = catalogue(vec![vec![0.0, 0.0], vec![0.0, 0.0]]);
+        catalogue.mutation_types[0] = String::from("DEL");
+        let counts = kmer_counts(&[("ACA", 10), ("TGT", 5), ("ATC", 2), ("GAT", 3)]);
+        assert!(opportunities(&catalogue, &counts).is_err());
+    }
+
+    #[test]
+    fn renormalize_keeps_the_mutations_of_each_sample() {
+        let cases: Vec<RenormalizeCase> = vec![
+            // same territory
+            (vec![vec![4.0, 1.0], vec![4.0, 3.0]], vec![10.0, 10.0], vec![20.0, 20.0], vec![vec![4.0, 1.0], vec![4.0, 3.0]]),
+            // the first context is three times as common in the target
+            (vec![vec![4.0, 1.0], vec![4.0, 3.0]], vec![10.0, 10.0], vec![30.0, 10.0], vec![vec![6.0, 2.0], vec![2.0, 2.0]]),
+            // contexts without sites in the source are 0
+            (vec![vec![4.0, 1.0], vec![4.0, 3.0]], vec![0.0, 10.0], vec![30.0, 10.0], vec![vec![0.0, 0.0], vec![8.0, 4.0]]),
+        ];
+        for (counts, source, target, expected) in cases {
+            let normalized = renormalize(&catalogue(counts), &source, &target);
+            for (row, expected_row) in normalized.counts.iter().zip(&expected) {
+                for (value, expected) in row.iter().zip(expected_row) {
+                    assert!((value - expected).abs() < 1e-9, "{:?} != {:?}", normalized.counts, expected_row);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rates_divide_by_the_opportunities() {
+        let normalized = rates(&catalogue(vec![vec![4.0, 1.0], vec![4.0, 3.0]]), &[2.0, 0.0]);
+        assert_eq!(normalized.counts, vec![vec![2.0, 0.5], vec![0.0, 0.0]]);
+    }
+}
@@ -3,10 +3,11 @@ mod cmd;
 
 use cmd::ms;
 use cmd::kmercount;
+use cmd::fit;
+use cmd::extract;
+use cmd::normalize;
 
 // LOGS
-use simplelog;
-
 use log::error;
 
 // ARGUMENTS
@@ -26,6 +27,12 @@ enum Commands {
     /// Adds files to myapp
     Ms(MsArgs),
     Kcount(KcountArgs),
+    /// Fits known mutational signatures to a catalogue
+    Fit(FitArgs),
+    /// Extracts de novo signatures from a catalogue with NMF
+    Extract(ExtractArgs),
+    /// Normalises a catalogue by the context opportunities of a kcount table
+    Normalize(NormalizeArgs),
 }
 
 #[derive(Args)]
@@ -51,13 +58,98 @@ struct KcountArgs {
     verbose: bool,
 }
 
+#[derive(Args)]
+struct FitArgs {
+    /// catalogue tsv (mutation types by samples)
+    catalogue: PathBuf,
+    /// signatures tsv (mutation types by signatures)
+    #[arg(short='s', long)]
+    signatures: PathBuf,
+    #[arg(short='o', long, default_value = "exposures.tsv")]
+    output: PathBuf,
+    /// signatures contributing less than this fraction are removed and the sample is refitted
+    #[arg(short='m', long, default_value_t = 0.0)]
+    min_exposure: f64,
+    /// number of bootstrap replicates for the confidence intervals
+    #[arg(short='b', long, default_value_t = 0)]
+    bootstrap: usize,
+    /// confidence level of the bootstrap intervals, between 0 and 1
+    #[arg(long, default_value_t = 0.95, value_parser = confidence_level)]
+    ci: f64,
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+    /// verbose flag
+    #[arg(short='v', long)]
+    verbose: bool,
+}
+
+#[derive(Args)]
+struct ExtractArgs {
+    /// catalogue tsv (mutation types by samples)
+    catalogue: PathBuf,
+    #[arg(long, default_value_t = 1, value_parser = at_least_one)]
+    min_rank: usize,
+    /// at least the minimum rank
+    #[arg(long, default_value_t = 10, value_parser = at_least_one)]
+    max_rank: usize,
+    /// number of random restarts for each rank
+    #[arg(short='n', long, default_value_t = 20, value_parser = at_least_one)]
+    restarts: usize,
+    /// number of multiplicative updates in each restart
+    #[arg(short='i', long, default_value_t = 2000)]
+    iterations: usize,
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+    /// prefix of the output files ({prefix}.ranks.tsv, {prefix}.k{rank}.signatures.tsv, ...)
+    #[arg(short='o', long, default_value = "extract")]
+    prefix: String,
+    /// verbose flag
+    #[arg(short='v', long)]
+    verbose: bool,
+}
+
+#[derive(Args)]
+struct NormalizeArgs {
+    /// catalogue tsv (mutation types by samples)
+    catalogue: PathBuf,
+    /// kcount json of the territory where the mutations were called
+    #[arg(short='k', long)]
+    kcount: PathBuf,
+    /// kcount json of a target territory, the spectra are renormalised to it instead of returning rates
+    #[arg(short='t', long)]
+    target: Option<PathBuf>,
+    #[arg(short='o', long, default_value = "normalized.tsv")]
+    output: PathBuf,
+    /// verbose flag
+    #[arg(short='v', long)]
+    verbose: bool,
+}
+
+// bootstrap confidence level, in (0, 1)
+fn confidence_level(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(level) if level > 0.0 && level < 1.0 => Ok(level),
+        Ok(_) => Err(String::from("needs to be between 0 and 1 (i.e. 0.95)")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// ranks and restarts
+fn at_least_one(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(number) if number >= 1 => Ok(number),
+        Ok(_) => Err(String::from("needs to be at least 1")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
 fn main() {
 
     let _ = simplelog::SimpleLogger::init(simplelog::LevelFilter::Info, simplelog::Config::default());
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Ms(args) => {
+        Commands::Ms(_args) => {
             ms::run();
         },
         Commands::Kcount(args) => {
@@ -68,6 +160,24 @@ fn main() {
                     error!("Error: {}", e);
                 }
             }
+        },
+        Commands::Fit(args) => {
+            let rres = fit::run(args.catalogue, args.signatures, args.output, args.min_exposure, args.bootstrap, args.ci, args.seed, args.verbose);
+            if let Err(e) = rres {
+                error!("Error: {}", e);
+            }
+        },
+        Commands::Extract(args) => {
+            let rres = extract::run(args.catalogue, args.min_rank, args.max_rank, args.restarts, args.iterations, args.seed, args.prefix, args.verbose);
+            if let Err(e) = rres {
+                error!("Error: {}", e);
+            }
+        },
+        Commands::Normalize(args) => {
+            let rres = normalize::run(args.catalogue, args.kcount, args.target, args.output, args.verbose);
+            if let Err(e) = rres {
+                error!("Error: {}", e);
+            }
         }
     }
 }
@@ -0,0 +1,278 @@
+
+
+use std::io::{self, BufRead, Write};
+
+use crate::sequence::{complement, is_pyrimidine, reverse_complement};
+
+// the catalogue is a matrix of mutation types (rows) by samples (columns),
+// stored as a tsv file with the same layout used by SigProfiler:
+//
+// MutationType  sample1  sample2
+// A[C>A]A       12       3
+//
+// values are f64 so the same type can hold counts, rates or exposures
+
+const SUBSTITUTIONS: [(u8, u8); 6] = [
+    (b'C', b'A'),
+    (b'C', b'G'),
+    (b'C', b'T'),
+    (b'T', b'A'),
+    (b'T', b'C'),
+    (b'T', b'G'),
+];
+
+const BASES: [u8; 4] = [b'A', b'C', b'G', b'T'];
+
+/// `Catalogue` represents a mutation type by sample matrix.
+///
+/// # Examples
+///
+/// ```
+/// use varianth_core::catalogue::{sbs_mutation_types, Catalogue};
+///
+/// let mut catalogue = Catalogue::new(sbs_mutation_types(1), vec![String::from("S1")]);
+/// catalogue.add("A[C>A]A", "S1", 2.0);
+///
+/// let mut buf = Vec::new();
+/// catalogue.write_tsv(&mut buf).unwrap();
+/// let read_back = Catalogue::read_tsv(&buf[..]).unwrap();
+///
+/// assert_eq!(read_back.mutation_types.len(), 96);
+/// assert_eq!(read_back.counts[0][0], 2.0);
+/// ```
+pub struct Catalogue {
+    pub mutation_types: Vec<String>,
+    pub samples: Vec<String>,
+    /// `counts[i][j]` is the value of mutation type `i` in sample `j`.
+    pub counts: Vec<Vec<f64>>,
+}
+
+impl Catalogue {
+    pub fn new(mutation_types: Vec<String>, samples: Vec<String>) -> Self {
+        let counts = vec![vec![0.0; samples.len()]; mutation_types.len()];
+        Self {
+            mutation_types,
+            samples,
+            counts,
+        }
+    }
+
+    pub fn mutation_type_index(&self, mutation_type: &str) -> Option<usize> {
+        self.mutation_types.iter().position(|x| x == mutation_type)
+    }
+
+    pub fn sample_index(&self, sample: &str) -> Option<usize> {
+        self.samples.iter().position(|x| x == sample)
+    }
+
+    /// Adds `value` to a cell, returns false if the mutation type or the
+    /// sample are not part of the catalogue.
+    pub fn add(&mut self, mutation_type: &str, sample: &str, value: f64) -> bool {
+        match (self.mutation_type_index(mutation_type), self.sample_index(sample)) {
+            (Some(i), Some(j)) => {
+                self.counts[i][j] += value;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Returns the values of sample `j` across all mutation types.
+    pub fn column(&self, j: usize) -> Vec<f64> {
+        self.counts.iter().map(|row| row[j]).collect()
+    }
+
+    pub fn read_tsv<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut lines = reader.lines();
+
+        let header = match lines.next() {
+            Some(line) => line?,
+            None => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "empty catalogue"));
+            }
+        };
+        let samples: Vec<String> = header.split('\t').skip(1).map(String::from).collect();
+
+        let mut mutation_types = Vec::new();
+        let mut counts = Vec::new();
+        for line in lines {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.split('\t');
+            // split always returns at least one field
+            let mutation_type = fields.next().unwrap_or_default().to_string();
+            let row: Vec<f64> = fields
+                .map(|x| x.parse::<f64>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if row.len() != samples.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("mutation type {} has {} values, expected {}", mutation_type, row.len(), samples.len()),
+                ));
+            }
+            mutation_types.push(mutation_type);
+            counts.push(row);
+        }
+
+        Ok(Self {
+            mutation_types,
+            samples,
+            counts,
+        })
+    }
+
+    pub fn write_tsv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "MutationType")?;
+        for sample in &self.samples {
+            write!(writer, "\t{}", sample)?;
+        }
+        writeln!(writer)?;
+
+        for (mutation_type, row) in self.mutation_types.iter().zip(&self.counts) {
+            write!(writer, "{}", mutation_type)?;
+            for value in row {
+                write!(writer, "\t{}", value)?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Cosine similarity of two spectra (i.e. columns of a catalogue), 0 if
+/// any of them is all zeros.
+///
+/// # Examples
+///
+/// ```
+/// use varianth_core::catalogue::cosine_similarity;
+///
+/// assert!((cosine_similarity(&[1.0, 2.0], &[2.0, 4.0]) - 1.0).abs() < 1e-12);
+/// assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 3.0]), 0.0);
+/// assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 1.0]), 0.0);
+/// ```
+pub fn cosine_similarity(a: &[f64], b: &[f64]) -> f64 {
+    let dot: f64 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a: f64 = a.iter().map(|x| x * x).sum::<f64>().sqrt();
+    let norm_b: f64 = b.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+fn kmers(k: usize) -> Vec<Vec<u8>> {
+    let mut out: Vec<Vec<u8>> = vec![Vec::new()];
+    for _ in 0..k {
+        out = out
+            .iter()
+            .flat_map(|prefix| {
+                BASES.iter().map(move |&base| {
+                    let mut kmer = prefix.clone();
+                    kmer.push(base);
+                    kmer
+                })
+            })
+            .collect();
+    }
+    out
+}
+
+/// Returns all the single base substitution types with `k` flanking bases
+/// at each side, in the canonical order (substitution, 5' context,
+/// 3' context). With `k = 1` these are the 96 SBS types.
+///
+/// # Examples
+///
+/// ```
+/// use varianth_core::catalogue::sbs_mutation_types;
+///
+/// let types = sbs_mutation_types(1);
+/// assert_eq!(types.len(), 96);
+/// assert_eq!(types[0], "A[C>A]A");
+/// assert_eq!(types[95], "T[T>G]T");
+/// ```
+pub fn sbs_mutation_types(k: usize) -> Vec<String> {
+    let flanks = kmers(k);
+    let mut out = Vec::with_capacity(SUBSTITUTIONS.len() * flanks.len() * flanks.len());
+    for (ref_base, alt_base) in SUBSTITUTIONS {
+        for left in &flanks {
+            for right in &flanks {
+                out.push(format_mutation_type(left, ref_base, alt_base, right));
+            }
+        }
+    }
+    out
+}
+
+fn format_mutation_type(left: &[u8], ref_base: u8, alt_base: u8, right: &[u8]) -> String {
+    format!(
+        "{}[{}>{}]{}",
+        String::from_utf8_lossy(left),
+        ref_base as char,
+        alt_base as char,
+        String::from_utf8_lossy(right),
+    )
+}
+
+/// Returns the reference context of a mutation type, i.e. `ACA` for
+/// `A[C>A]A`.
+///
+/// # Examples
+///
+/// ```
+/// use varianth_core::catalogue::mutation_type_context;
+///
+/// assert_eq!(mutation_type_context("A[C>A]T"), Some(String::from("ACT")));
+/// assert_eq!(mutation_type_context("ACT"), None);
+/// ```
+pub fn mutation_type_context(mutation_type: &str) -> Option<String> {
+    let (left, rest) = mutation_type.split_once('[')?;
+    let (substitution, right) = rest.split_once(']')?;
+    let (ref_base, _) = substitution.split_once('>')?;
+    Some(format!("{}{}{}", left, ref_base, right))
+}
+
+/// Classifies a substitution given its reference context (an odd length
+/// sequence centered on the mutated base) and the alternative base.
+/// The result is collapsed to the pyrimidine reference strand.
+///
+/// Returns `None` if the context contains non ACGT bases or if the
+/// alternative base is the same as the reference.
+///
+/// # Examples
+///
+/// ```
+/// use varianth_core::catalogue::sbs_mutation_type;
+///
+/// assert_eq!(sbs_mutation_type(b"ACA", b'A'), Some(String::from("A[C>A]A")));
+/// // G>T in TGT is reported as its reverse complement
+/// assert_eq!(sbs_mutation_type(b"TGT", b'T'), Some(String::from("A[C>A]A")));
+/// assert_eq!(sbs_mutation_type(b"ANA", b'A'), None);
+/// ```
+pub fn sbs_mutation_type(context: &[u8], alt: u8) -> Option<String> {
+    if context.len().is_multiple_of(2) {
+        return None;
+    }
+    let context = context.to_ascii_uppercase();
+    let alt = alt.to_ascii_uppercase();
+    if !context.iter().chain(std::iter::once(&alt)).all(|x| BASES.contains(x)) {
+        return None;
+    }
+
+    let k = context.len() / 2;
+    if context[k] == alt {
+        return None;
+    }
+
+    let (context, alt) = if is_pyrimidine(context[k]) {
+        (context, alt)
+    } else {
+        (reverse_complement(&context), complement(alt))
+    };
+
+    Some(format_mutation_type(&context[..k], context[k], alt, &context[k + 1..]))
+}
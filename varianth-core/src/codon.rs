@@ -0,0 +1,82 @@
+
+
+// genetic codes of the NCBI, the amino acids of the 64 codons in the order
+// of the NCBI tables (TTT, TTC, TTA, TTG, TCT, ...), * is a stop codon
+const GENETIC_CODES: [(u8, &str, &[u8; 64]); 19] = [
+    (1, "Standard", b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (2, "Vertebrate Mitochondrial", b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSS**VVVVAAAADDEEGGGG"),
+    (3, "Yeast Mitochondrial", b"FFLLSSSSYY**CCWWTTTTPPPPHHQQRRRRIIMMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (4, "Mold, Protozoan and Coelenterate Mitochondrial, Mycoplasma", b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (5, "Invertebrate Mitochondrial", b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSSSVVVVAAAADDEEGGGG"),
+    (6, "Ciliate, Dasycladacean and Hexamita Nuclear", b"FFLLSSSSYYQQCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (9, "Echinoderm and Flatworm Mitochondrial", b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG"),
+    (10, "Euplotid Nuclear", b"FFLLSSSSYY**CCCWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (11, "Bacterial, Archaeal and Plant Plastid", b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (12, "Alternative Yeast Nuclear", b"FFLLSSSSYY**CC*WLLLSPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (13, "Ascidian Mitochondrial", b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNKKSSGGVVVVAAAADDEEGGGG"),
+    (14, "Alternative Flatworm Mitochondrial", b"FFLLSSSSYYY*CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNNKSSSSVVVVAAAADDEEGGGG"),
+    (16, "Chlorophycean Mitochondrial", b"FFLLSSSSYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (21, "Trematode Mitochondrial", b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIMMTTTTNNNKSSSSVVVVAAAADDEEGGGG"),
+    (22, "Scenedesmus obliquus Mitochondrial", b"FFLLSS*SYY*LCC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (23, "Thraustochytrium Mitochondrial", b"FF*LSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (24, "Rhabdopleuridae Mitochondrial", b"FFLLSSSSYY**CCWWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSSKVVVVAAAADDEEGGGG"),
+    (25, "Candidate Division SR1 and Gracilibacteria", b"FFLLSSSSYY**CCGWLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+    (26, "Pachysolen tannophilus Nuclear", b"FFLLSSSSYY**CC*WLLLAPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG"),
+];
+
+/// A translation table of the NCBI, by its number.
+///
+/// # Examples
+///
+/// ```
+/// use varianth_core::codon::GeneticCode;
+///
+/// let standard = GeneticCode::ncbi(1).unwrap();
+/// assert_eq!(standard.translate(b"ATG"), Some(b'M'));
+/// assert_eq!(standard.translate(b"tga"), Some(b'*'));
+/// assert_eq!(standard.translate(b"AT-"), None);
+///
+/// let mitochondrial = GeneticCode::ncbi(2).unwrap();
+/// assert_eq!(mitochondrial.translate(b"TGA"), Some(b'W'));
+/// assert!(GeneticCode::ncbi(7).is_none());
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct GeneticCode {
+    pub id: u8,
+    pub name: &'static str,
+    amino_acids: &'static [u8; 64],
+}
+
+impl GeneticCode {
+    pub fn ncbi(id: u8) -> Option<Self> {
+        GENETIC_CODES
+            .iter()
+            .find(|(code_id, _, _)| *code_id == id)
+            .map(|&(id, name, amino_acids)| GeneticCode { id, name, amino_acids })
+    }
+
+    /// Numbers of the available tables.
+    pub fn ids() -> Vec<u8> {
+        GENETIC_CODES.iter().map(|(id, _, _)| *id).collect()
+    }
+
+    /// Amino acid (one letter code) of a codon, None if it is not three
+    /// A, C, G or T bases.
+    pub fn translate(&self, codon: &[u8]) -> Option<u8> {
+        if codon.len() != 3 {
+            return None;
+        }
+        let mut idx = 0;
+        for base in codon {
+            let value = match base.to_ascii_uppercase() {
+                b'T' => 0,
+                b'C' => 1,
+                b'A' => 2,
+                b'G' => 3,
+                _ => return None,
+            };
+            idx = idx * 4 + value;
+        }
+        Some(self.amino_acids[idx])
+    }
+}
@@ -1,3 +1,5 @@
 
 pub mod position;
-
+pub mod sequence;
+pub mod catalogue;
+pub mod codon;
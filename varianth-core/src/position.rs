@@ -52,10 +52,10 @@ impl CheckedSub for NonZeroUsize {
 /// use varianth_core::position::Contig;
 ///
 /// let length = NonZeroUsize::new(248956422).unwrap();
-/// let contig = Contig::new("chr1", length);
+/// let contig = Contig::new("chr1", Some(length));
 ///
 /// assert_eq!(contig.name, BString::from("chr1"));
-/// assert_eq!(contig.length, length);
+/// assert_eq!(contig.length, Some(length));
 /// ```
 pub struct Contig {
     pub name: BString,
@@ -84,7 +84,7 @@ impl Contig {
 /// use varianth_core::position::{Contig, Position};
 ///
 /// let length = NonZeroUsize::new(248956422).unwrap();
-/// let contig = Contig::new("chr1", length);
+/// let contig = Contig::new("chr1", Some(length));
 /// let position = NonZeroUsize::new(2489564).unwrap();
 /// let width = NonZeroU8::new(10).unwrap();
 ///
@@ -0,0 +1,47 @@
+
+
+// helpers to work with nucleotide sequences stored as bytes,
+// anything that is not ACGT (upper or lower case) is returned as N
+
+/// Returns the complement of a single nucleotide.
+///
+/// # Examples
+///
+/// ```
+/// use varianth_core::sequence::complement;
+///
+/// assert_eq!(complement(b'A'), b'T');
+/// assert_eq!(complement(b'g'), b'c');
+/// assert_eq!(complement(b'R'), b'N');
+/// ```
+pub fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        b'a' => b't',
+        b'c' => b'g',
+        b'g' => b'c',
+        b't' => b'a',
+        _ => b'N',
+    }
+}
+
+/// Returns the reverse complement of a sequence.
+///
+/// # Examples
+///
+/// ```
+/// use varianth_core::sequence::reverse_complement;
+///
+/// assert_eq!(reverse_complement(b"ACCGT"), b"ACGGT".to_vec());
+/// ```
+pub fn reverse_complement(sequence: &[u8]) -> Vec<u8> {
+    sequence.iter().rev().map(|&base| complement(base)).collect()
+}
+
+/// Returns true if the base is a pyrimidine (C or T).
+pub fn is_pyrimidine(base: u8) -> bool {
+    matches!(base.to_ascii_uppercase(), b'C' | b'T')
+}